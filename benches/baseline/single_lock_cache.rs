@@ -0,0 +1,216 @@
+//! `src/cache.rs` as of 6d56ec7, when the redirect cache got lru eviction and before it was
+//! sharded. Kept unchanged so `benches/cache.rs` compares the sharded cache with the code it
+//! replaced, rather than with a reimplementation of it.
+
+use lru::LruCache;
+use std::{
+    fmt::Write,
+    num::NonZeroUsize,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::RwLock, task::JoinHandle};
+
+#[derive(Debug, Clone)]
+struct CacheEntry<V> {
+    value: V,
+    expiry: Instant,
+    /// Hard limit the sliding expiry is never moved past
+    deadline: Option<Instant>,
+}
+
+/// A cache with a sliding ttl per entry, holding at most `capacity` entries.
+///
+/// Once full, inserting drops the least recently used entry.
+#[derive(Clone, Debug)]
+pub struct TtlCache<V> {
+    map: Arc<RwLock<LruCache<String, CacheEntry<V>>>>,
+    ttl: Duration,
+    metrics: Arc<CacheMetrics>,
+}
+
+impl<V: Clone + Send + Sync + 'static> TtlCache<V> {
+    /// Create a new cache and start the cleaner immediately
+    pub async fn new(
+        ttl: Duration,
+        cleanup_interval: Duration,
+        capacity: NonZeroUsize,
+    ) -> (Self, JoinHandle<()>) {
+        let cache = Self {
+            map: Arc::new(RwLock::new(LruCache::new(capacity))),
+            ttl,
+            metrics: Arc::default(),
+        };
+
+        let cleaner = cache._spawn_cleaner(cleanup_interval);
+        (cache, cleaner)
+    }
+
+    pub async fn insert(&self, key: String, value: V) {
+        let expiry = Instant::now() + self.ttl;
+        let entry = CacheEntry {
+            value,
+            expiry,
+            deadline: None,
+        };
+        self.put(key, entry).await;
+    }
+
+    /// Insert a value that must not be served after `deadline`, regardless of how often it's read
+    pub async fn insert_until(&self, key: String, value: V, deadline: Instant) {
+        let expiry = (Instant::now() + self.ttl).min(deadline);
+        let entry = CacheEntry {
+            value,
+            expiry,
+            deadline: Some(deadline),
+        };
+        self.put(key, entry).await;
+    }
+
+    async fn put(&self, key: String, entry: CacheEntry<V>) {
+        let mut map = self.map.write().await;
+        // `push` also hands back the old entry when the key was already cached
+        if let Some((evicted, _)) = map.push(key.clone(), entry)
+            && evicted != key
+        {
+            self.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop an entry right away, e.g. because the underlying value changed
+    pub async fn remove(&self, key: &str) {
+        self.map.write().await.pop(key);
+    }
+
+    pub async fn get(&self, key: &str) -> Option<V> {
+        let mut map = self.map.write().await;
+        if let Some(entry) = map.get_mut(key) {
+            let now = Instant::now();
+            if now < entry.expiry {
+                // sliding TTL: reset expiry, but never past the deadline
+                entry.expiry = match entry.deadline {
+                    Some(deadline) => (now + self.ttl).min(deadline),
+                    None => now + self.ttl,
+                };
+                self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entry.value.clone());
+            } else {
+                map.pop(key);
+                self.metrics.expirations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Counters since startup, plus the current size
+    pub async fn metrics(&self) -> CacheMetricsSnapshot {
+        let map = self.map.read().await;
+        CacheMetricsSnapshot {
+            hits: self.metrics.hits.load(Ordering::Relaxed),
+            misses: self.metrics.misses.load(Ordering::Relaxed),
+            evictions: self.metrics.evictions.load(Ordering::Relaxed),
+            expirations: self.metrics.expirations.load(Ordering::Relaxed),
+            entries: map.len(),
+            capacity: map.cap().get(),
+        }
+    }
+
+    /// Internal: spawn the background cleaner
+    fn _spawn_cleaner(&self, interval: Duration) -> JoinHandle<()> {
+        let map = self.map.clone();
+        let metrics = self.metrics.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let mut map = map.write().await;
+                let now = Instant::now();
+                let expired: Vec<String> = map
+                    .iter()
+                    .filter(|(_, entry)| entry.expiry <= now)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for key in &expired {
+                    map.pop(key);
+                }
+                metrics
+                    .expirations
+                    .fetch_add(expired.len() as u64, Ordering::Relaxed);
+                println!("🧹 Cache cleaned, {} keys remain", map.len());
+            }
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Entries dropped to make room, before they expired
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheMetricsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl CacheMetricsSnapshot {
+    /// Appends the metrics in the prometheus text format, every name starts with `prefix`
+    pub fn write_prometheus(&self, prefix: &str, out: &mut String) {
+        let metrics = [
+            (
+                "hits_total",
+                "counter",
+                "Lookups answered from the cache",
+                self.hits,
+            ),
+            (
+                "misses_total",
+                "counter",
+                "Lookups that had to go to the database",
+                self.misses,
+            ),
+            (
+                "evictions_total",
+                "counter",
+                "Entries dropped to stay within the capacity",
+                self.evictions,
+            ),
+            (
+                "expirations_total",
+                "counter",
+                "Entries dropped because their ttl ran out",
+                self.expirations,
+            ),
+            (
+                "entries",
+                "gauge",
+                "Entries currently cached",
+                self.entries as u64,
+            ),
+            (
+                "capacity",
+                "gauge",
+                "Most entries the cache holds",
+                self.capacity as u64,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            // writing to a String can't fail
+            let _ = writeln!(out, "# HELP {prefix}_{name} {help}");
+            let _ = writeln!(out, "# TYPE {prefix}_{name} {kind}");
+            let _ = writeln!(out, "{prefix}_{name} {value}");
+        }
+    }
+}
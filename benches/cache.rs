@@ -0,0 +1,121 @@
+//! Compares the sharded redirect cache with a cache behind a single lock, under concurrent lookups.
+//!
+//! Run with `cargo bench --bench cache`. Every task looks up random keys of a full cache, one in
+//! a hundred operations replaces a key. The single lock cache is `TtlCache` as it was before it
+//! was sharded: every lookup takes the write lock to slide the expiry and update the lru order.
+
+#[path = "../src/cache.rs"]
+#[allow(dead_code)]
+mod cache;
+
+#[path = "baseline/single_lock_cache.rs"]
+#[allow(dead_code)]
+mod single_lock_cache;
+
+use cache::CacheBackend;
+use std::{
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const KEYS: usize = 10_000;
+const OPS_PER_TASK: usize = 200_000;
+const TASKS: [usize; 4] = [1, 4, 16, 64];
+const TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+enum Subject {
+    SingleLock(single_lock_cache::TtlCache<String>),
+    Sharded(cache::TtlCache<String>),
+}
+
+impl Subject {
+    async fn insert(&self, key: String, value: String) {
+        match self {
+            Self::SingleLock(cache) => cache.insert(key, value).await,
+            Self::Sharded(cache) => cache.insert(key, value).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Option<String> {
+        match self {
+            Self::SingleLock(cache) => cache.get(key).await,
+            Self::Sharded(cache) => cache.get(key).await,
+        }
+    }
+}
+
+/// Cheap pseudo random numbers, so the generator doesn't dominate the measurement
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Lookups per second over all tasks
+async fn run(subject: Subject, keys: Arc<Vec<String>>, tasks: usize) -> f64 {
+    for key in keys.iter() {
+        subject
+            .insert(key.clone(), format!("https://example.com/{key}"))
+            .await;
+    }
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let subject = subject.clone();
+            let keys = keys.clone();
+            tokio::spawn(async move {
+                let mut state = task as u64 * 0x9E37_79B9_7F4A_7C15 + 1;
+                for _ in 0..OPS_PER_TASK {
+                    let key = &keys[xorshift(&mut state) as usize % keys.len()];
+                    if xorshift(&mut state).is_multiple_of(100) {
+                        subject.insert(key.clone(), key.clone()).await;
+                    } else {
+                        std::hint::black_box(subject.get(key).await);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.expect("benchmark task panicked");
+    }
+    (tasks * OPS_PER_TASK) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("unable to start the runtime");
+    let workers = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    // room to spare, keys are not spread perfectly evenly over the shards and a full shard
+    // would evict, making the sharded cache answer some lookups with cheaper misses
+    let capacity = NonZeroUsize::new(KEYS * 2).expect("KEYS is not zero");
+    let keys: Arc<Vec<String>> = Arc::new((0..KEYS).map(|i| format!("key{i}")).collect());
+
+    println!("{workers} worker threads, {KEYS} keys, {OPS_PER_TASK} operations per task");
+    println!(
+        "{:>6} {:>16} {:>16} {:>8}",
+        "tasks", "single lock/s", "sharded/s", "speedup"
+    );
+    runtime.block_on(async {
+        for tasks in TASKS {
+            let (single_lock_cache, cleaner) =
+                single_lock_cache::TtlCache::new(TTL, Duration::from_secs(3600), capacity).await;
+            let single = run(Subject::SingleLock(single_lock_cache), keys.clone(), tasks).await;
+            cleaner.abort();
+            let (sharded_cache, cleaner) =
+                cache::TtlCache::new(TTL, Duration::from_secs(3600), capacity).await;
+            let sharded = run(Subject::Sharded(sharded_cache), keys.clone(), tasks).await;
+            cleaner.abort();
+            println!(
+                "{tasks:>6} {single:>16.0} {sharded:>16.0} {:>7.2}x",
+                sharded / single
+            );
+        }
+    });
+}
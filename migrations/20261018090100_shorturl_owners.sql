@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE shorturls ADD COLUMN owner_id INT REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX shorturls_owner_id_idx ON shorturls (owner_id, created_at);
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
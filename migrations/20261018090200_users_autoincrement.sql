@@ -0,0 +1,46 @@
+-- Add migration script here
+-- `users.id` was declared as `INT PRIMARY KEY`, which is not an alias for the rowid in sqlite
+-- and therefore never auto increments. Sqlite can't change a column definition in place,
+-- so the table is rebuilt (see https://www.sqlite.org/lang_altertable.html#otheralter).
+-- Migrations run in a transaction, where foreign keys can't be turned off, so dropping the old
+-- table cascades into `sessions` and `shorturls`. What references users is kept aside and
+-- restored afterwards.
+CREATE TEMP TABLE sessions_backup AS SELECT * FROM sessions;
+CREATE TEMP TABLE shorturl_owners_backup AS
+SELECT shorturl, owner_id FROM shorturls WHERE owner_id IS NOT NULL;
+
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    email TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+INSERT INTO users_new (id, email, password_hash, name, created_at, is_admin)
+SELECT id, email, password_hash, name, created_at, is_admin FROM users;
+
+DROP TABLE users;
+
+ALTER TABLE users_new RENAME TO users;
+
+-- replacing, in case foreign keys were off and nothing was cascaded
+INSERT OR REPLACE INTO sessions SELECT * FROM sessions_backup;
+UPDATE shorturls SET owner_id = (
+    SELECT owner_id FROM shorturl_owners_backup WHERE shorturl_owners_backup.shorturl = shorturls.shorturl
+) WHERE shorturl IN (SELECT shorturl FROM shorturl_owners_backup);
+
+DROP TABLE sessions_backup;
+DROP TABLE shorturl_owners_backup;
+
+CREATE TABLE invites (
+    token TEXT PRIMARY KEY NOT NULL,
+    email TEXT COLLATE NOCASE,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    used_at TIMESTAMP
+);
+
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Counter for the sequential and hashids short code strategies, it only ever goes up
+-- so a number is never handed out twice, even after links get deleted.
+CREATE TABLE short_code_sequence (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    value INTEGER NOT NULL
+);
+
+INSERT INTO short_code_sequence (id, value) VALUES (1, 0);
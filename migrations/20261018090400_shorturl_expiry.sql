@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE shorturls ADD COLUMN expires_at TIMESTAMP;
+
+ALTER TABLE shorturls ADD COLUMN max_clicks INTEGER;
+
+-- Only counted for links with `max_clicks`, those are never cached so every visit reaches the database
+ALTER TABLE shorturls ADD COLUMN click_count INTEGER NOT NULL DEFAULT 0;
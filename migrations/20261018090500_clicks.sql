@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE clicks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    shorturl TEXT NOT NULL REFERENCES shorturls(shorturl) ON DELETE CASCADE,
+    clicked_at TIMESTAMP NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    -- salted sha256 of the visitor ip, enough to count unique visitors without storing the ip
+    ip_hash TEXT
+);
+
+CREATE INDEX clicks_shorturl_clicked_at_idx ON clicks (shorturl, clicked_at);
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- domains and host patterns links can't point to, on top of the ones from BLOCKLIST_FILE
+CREATE TABLE blocklist (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    pattern TEXT NOT NULL UNIQUE,
+    created_by INT REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- links disabled by an admin stop redirecting but are kept around
+ALTER TABLE shorturls ADD COLUMN disabled_at TIMESTAMP;
+
+CREATE TABLE reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    shorturl TEXT NOT NULL REFERENCES shorturls(shorturl) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    reporter_ip_hash TEXT,
+    created_at TIMESTAMP NOT NULL,
+    resolved_at TIMESTAMP
+);
+
+-- one open report per link and visitor is enough
+CREATE UNIQUE INDEX reports_open_idx ON reports (shorturl, reporter_ip_hash) WHERE resolved_at IS NULL;
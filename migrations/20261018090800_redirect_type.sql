@@ -0,0 +1,3 @@
+-- Add migration script here
+-- status code of the redirect, NULL follows the server default
+ALTER TABLE shorturls ADD COLUMN redirect_type INTEGER;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE shorturls ADD COLUMN passthrough BOOLEAN NOT NULL DEFAULT FALSE;
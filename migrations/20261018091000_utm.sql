@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE shorturls ADD COLUMN utm_source TEXT;
+ALTER TABLE shorturls ADD COLUMN utm_medium TEXT;
+ALTER TABLE shorturls ADD COLUMN utm_campaign TEXT;
+ALTER TABLE shorturls ADD COLUMN utm_term TEXT;
+ALTER TABLE shorturls ADD COLUMN utm_content TEXT;
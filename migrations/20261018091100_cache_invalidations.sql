@@ -0,0 +1,11 @@
+-- Add migration script here
+-- short urls changed by an instance, the others poll this to drop them from their caches
+CREATE TABLE cache_invalidations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    shorturl TEXT NOT NULL,
+    -- the instance that made the change, it already dropped the short url itself
+    origin TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX cache_invalidations_created_at_idx ON cache_invalidations (created_at);
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- adds the `manage` scope between `create` and `admin`. Sqlite can't change a check constraint
+-- in place, so the table is rebuilt (see https://www.sqlite.org/lang_altertable.html#otheralter).
+-- Nothing references `api_tokens`, so foreign keys can stay on.
+CREATE TABLE api_tokens_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- sha256 of the token, the token itself is only shown once
+    token_hash TEXT NOT NULL UNIQUE,
+    scope TEXT NOT NULL CHECK (scope IN ('read', 'create', 'manage', 'admin')),
+    created_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP
+);
+
+INSERT INTO api_tokens_new (id, user_id, name, token_hash, scope, created_at, last_used_at)
+SELECT id, user_id, name, token_hash, scope, created_at, last_used_at FROM api_tokens;
+
+DROP TABLE api_tokens;
+
+ALTER TABLE api_tokens_new RENAME TO api_tokens;
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
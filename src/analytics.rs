@@ -0,0 +1,529 @@
+use axum::http::{HeaderMap, header};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+use std::{net::IpAddr, time::Duration};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// How many clicks can wait for the recorder before new ones get dropped
+pub const CLICK_CHANNEL_CAPACITY: usize = 10_000;
+/// Most clicks written in a single transaction
+const BATCH_SIZE: usize = 500;
+/// How long the recorder waits for a batch to fill up before writing it anyway
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Referrers and user agents are client controlled, don't store arbitrarily long ones
+const MAX_HEADER_LENGTH: usize = 512;
+
+/// A single visit of a short url
+#[derive(Debug, Clone)]
+pub struct ClickEvent {
+    pub shorturl: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_hash: Option<String>,
+}
+
+impl ClickEvent {
+    pub fn new(shorturl: String, headers: &HeaderMap, ip: Option<IpAddr>, ip_salt: &str) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.chars().take(MAX_HEADER_LENGTH).collect::<String>())
+        };
+
+        Self {
+            shorturl,
+            clicked_at: Utc::now(),
+            referrer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+            ip_hash: ip.map(|ip| hash_ip(ip, ip_salt)),
+        }
+    }
+}
+
+/// The visitor ip, taken from the first `X-Forwarded-For` entry when running behind a trusted proxy
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr, trust_proxy_headers: bool) -> IpAddr {
+    if !trust_proxy_headers {
+        return peer;
+    }
+    headers
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+pub fn hash_ip(ip: IpAddr, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Spawn the task writing clicks to the database.
+///
+/// The task finishes once every sender is dropped and the remaining clicks are written,
+/// so awaiting the handle after the server stopped flushes everything.
+pub fn spawn_click_recorder(
+    sqlite_pool: Pool<Sqlite>,
+    mut rx: mpsc::Receiver<ClickEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        loop {
+            // wait for the first click, then give the batch some time to fill up
+            match rx.recv().await {
+                Some(event) => batch.push(event),
+                None => break,
+            }
+            let flush = tokio::time::sleep(FLUSH_INTERVAL);
+            tokio::pin!(flush);
+            while batch.len() < BATCH_SIZE {
+                tokio::select! {
+                    event = rx.recv() => match event {
+                        Some(event) => batch.push(event),
+                        None => break,
+                    },
+                    _ = &mut flush => break,
+                }
+            }
+
+            if let Err(e) = write_batch(&sqlite_pool, &batch).await {
+                tracing::error!("Unable to record {} clicks: {}", batch.len(), e);
+            }
+            batch.clear();
+        }
+        tracing::info!("Click recorder stopped");
+    })
+}
+
+async fn write_batch(sqlite_pool: &Pool<Sqlite>, batch: &[ClickEvent]) -> Result<(), sqlx::Error> {
+    let mut tx = sqlite_pool.begin().await?;
+    for event in batch {
+        // a failing insert (e.g. the link was deleted in the meantime) only skips that click
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO clicks (shorturl, clicked_at, referrer, user_agent, ip_hash) VALUES (?, ?, ?, ?, ?)",
+            event.shorturl,
+            event.clicked_at,
+            event.referrer,
+            event.user_agent,
+            event.ip_hash
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            tracing::debug!("Skipping click on {}: {}", event.shorturl, e);
+        }
+    }
+    tx.commit().await?;
+    tracing::debug!("Recorded {} clicks", batch.len());
+    Ok(())
+}
+
+/// Read side of the click stats
+#[derive(Clone, Debug)]
+pub struct StatsStore {
+    sqlite_pool: Pool<Sqlite>,
+}
+
+/// How many days and hours the clicks-over-time charts cover
+const DAILY_BUCKETS: i64 = 30;
+const HOURLY_BUCKETS: i64 = 48;
+/// How many referrers are listed
+const TOP_REFERRERS: i64 = 10;
+
+impl StatsStore {
+    pub fn new(sqlite_pool: Pool<Sqlite>) -> Self {
+        Self { sqlite_pool }
+    }
+
+    pub async fn link_stats(&self, shorturl: &str) -> Result<LinkStats, sqlx::Error> {
+        self.combined_stats(&[shorturl.to_string()]).await
+    }
+
+    /// The stats of several links added up, e.g. of every link of a campaign
+    pub async fn combined_stats(&self, shorturls: &[String]) -> Result<LinkStats, sqlx::Error> {
+        // passed as a json array, sqlite has no other way to bind a list
+        let shorturls = serde_json::to_string(shorturls).expect("strings serialize to json");
+        let shorturls = shorturls.as_str();
+        let totals = sqlx::query!(
+            r#"SELECT COUNT(*) as "total!: i64", COUNT(DISTINCT ip_hash) as "unique_visitors!: i64"
+            FROM clicks WHERE shorturl IN (SELECT value FROM json_each(?))"#,
+            shorturls
+        )
+        .fetch_one(&self.sqlite_pool)
+        .await?;
+
+        let now = Utc::now();
+        let daily = self
+            .buckets(
+                shorturls,
+                "%Y-%m-%d",
+                now - chrono::Duration::days(DAILY_BUCKETS - 1),
+            )
+            .await?;
+        let hourly = self
+            .buckets(
+                shorturls,
+                "%Y-%m-%d %H:00",
+                now - chrono::Duration::hours(HOURLY_BUCKETS - 1),
+            )
+            .await?;
+
+        let top_referrers = sqlx::query!(
+            r#"SELECT referrer, COUNT(*) as "count!: i64" FROM clicks WHERE shorturl IN (SELECT value FROM json_each(?))
+            GROUP BY referrer ORDER BY 2 DESC LIMIT ?"#,
+            shorturls,
+            TOP_REFERRERS
+        )
+        .map(|row| {
+            (
+                row.referrer.unwrap_or_else(|| "Direct".to_string()),
+                row.count,
+            )
+        })
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+
+        // user agents are parsed here rather than on insert, so the raw value stays available
+        let user_agents = sqlx::query!(
+            r#"SELECT user_agent, COUNT(*) as "count!: i64" FROM clicks WHERE shorturl IN (SELECT value FROM json_each(?)) GROUP BY user_agent"#,
+            shorturls
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+        let parser = woothee::parser::Parser::new();
+        let mut browsers = Breakdown::default();
+        let mut operating_systems = Breakdown::default();
+        for row in user_agents {
+            let parsed = row.user_agent.as_deref().and_then(|ua| parser.parse(ua));
+            let (browser, os) = match parsed {
+                Some(ua) => (ua.name.to_string(), ua.os.to_string()),
+                None => ("UNKNOWN".to_string(), "UNKNOWN".to_string()),
+            };
+            browsers.add(browser, row.count);
+            operating_systems.add(os, row.count);
+        }
+
+        Ok(LinkStats {
+            total_clicks: totals.total,
+            unique_visitors: totals.unique_visitors,
+            daily: fill_buckets(
+                daily,
+                now,
+                DAILY_BUCKETS,
+                chrono::Duration::days(1),
+                "%Y-%m-%d",
+            ),
+            hourly: fill_buckets(
+                hourly,
+                now,
+                HOURLY_BUCKETS,
+                chrono::Duration::hours(1),
+                "%Y-%m-%d %H:00",
+            ),
+            top_referrers,
+            browsers: browsers.into_sorted(),
+            operating_systems: operating_systems.into_sorted(),
+        })
+    }
+
+    /// Click counts grouped by `clicked_at` formatted with the sqlite strftime `format`,
+    /// `shorturls` is a json array
+    async fn buckets(
+        &self,
+        shorturls: &str,
+        format: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT strftime(?, clicked_at) as "bucket!: String", COUNT(*) as "count!: i64"
+            FROM clicks WHERE shorturl IN (SELECT value FROM json_each(?)) AND clicked_at >= ? GROUP BY 1"#,
+            format,
+            shorturls,
+            since
+        )
+        .map(|row| (row.bucket, row.count))
+        .fetch_all(&self.sqlite_pool)
+        .await
+    }
+}
+
+/// Turns sparse `(bucket, count)` pairs into `len` consecutive buckets ending at `now`
+fn fill_buckets(
+    counts: Vec<(String, i64)>,
+    now: DateTime<Utc>,
+    len: i64,
+    step: chrono::Duration,
+    format: &str,
+) -> Vec<(String, i64)> {
+    let counts: std::collections::HashMap<_, _> = counts.into_iter().collect();
+    (0..len)
+        .rev()
+        .map(|i| {
+            let label = (now - step * i as i32).format(format).to_string();
+            let count = counts.get(&label).copied().unwrap_or(0);
+            (label, count)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Breakdown(std::collections::HashMap<String, i64>);
+
+impl Breakdown {
+    fn add(&mut self, key: String, count: i64) {
+        *self.0.entry(key).or_default() += count;
+    }
+
+    fn into_sorted(self) -> Vec<(String, i64)> {
+        let mut entries: Vec<_> = self.0.into_iter().collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        entries
+    }
+}
+
+/// Everything shown on the stats page of a link
+#[derive(Debug, Clone)]
+pub struct LinkStats {
+    pub total_clicks: i64,
+    /// Distinct hashed ips
+    pub unique_visitors: i64,
+    /// `(day, clicks)`, oldest first
+    pub daily: Vec<(String, i64)>,
+    /// `(hour, clicks)`, oldest first
+    pub hourly: Vec<(String, i64)>,
+    pub top_referrers: Vec<(String, i64)>,
+    pub browsers: Vec<(String, i64)>,
+    pub operating_systems: Vec<(String, i64)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{moderation::Blocklist, test_utils};
+    use std::time::Instant;
+
+    /// A database with the links `shorturls`
+    async fn pool_with_links(shorturls: &[&str]) -> Pool<Sqlite> {
+        let pool = test_utils::pool().await;
+        let owner = test_utils::user(&pool, "owner@example.com", false).await;
+        test_utils::links(&pool, owner.id, shorturls).await;
+        pool
+    }
+
+    fn click(shorturl: &str, clicked_at: DateTime<Utc>) -> ClickEvent {
+        ClickEvent {
+            shorturl: shorturl.to_string(),
+            clicked_at,
+            referrer: None,
+            user_agent: None,
+            ip_hash: None,
+        }
+    }
+
+    async fn recorded_clicks(pool: &Pool<Sqlite>) -> i64 {
+        sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM clicks"#)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn clicks_are_written_once_the_batch_had_time_to_fill_up() {
+        let pool = pool_with_links(&["abc"]).await;
+        let (tx, rx) = mpsc::channel(10);
+        let recorder = spawn_click_recorder(pool.clone(), rx);
+
+        tx.send(click("abc", Utc::now())).await.unwrap();
+        tx.send(click("abc", Utc::now())).await.unwrap();
+        tokio::time::sleep(FLUSH_INTERVAL / 4).await;
+        assert_eq!(recorded_clicks(&pool).await, 0);
+
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        assert_eq!(recorded_clicks(&pool).await, 2);
+        assert!(!recorder.is_finished());
+        drop(tx);
+        recorder.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_batches_are_written_right_away() {
+        let pool = pool_with_links(&["abc"]).await;
+        let (tx, rx) = mpsc::channel(BATCH_SIZE);
+        let recorder = spawn_click_recorder(pool.clone(), rx);
+
+        for _ in 0..BATCH_SIZE {
+            tx.send(click("abc", Utc::now())).await.unwrap();
+        }
+        tokio::time::sleep(FLUSH_INTERVAL / 2).await;
+        assert_eq!(recorded_clicks(&pool).await, BATCH_SIZE as i64);
+        drop(tx);
+        recorder.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_clicks_are_written_when_the_senders_are_gone() {
+        let pool = pool_with_links(&["abc"]).await;
+        let (tx, rx) = mpsc::channel(10);
+        let recorder = spawn_click_recorder(pool.clone(), rx);
+
+        tx.send(click("abc", Utc::now())).await.unwrap();
+        tx.send(click("abc", Utc::now())).await.unwrap();
+        let started = Instant::now();
+        drop(tx);
+        recorder.await.unwrap();
+        assert!(started.elapsed() < FLUSH_INTERVAL);
+        assert_eq!(recorded_clicks(&pool).await, 2);
+    }
+
+    #[tokio::test]
+    async fn clicks_on_deleted_links_are_skipped() {
+        let pool = pool_with_links(&["abc"]).await;
+        let batch = [click("abc", Utc::now()), click("gone", Utc::now())];
+        write_batch(&pool, &batch).await.unwrap();
+        assert_eq!(recorded_clicks(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn clicks_are_dropped_while_the_recorder_is_behind() {
+        let pool = pool_with_links(&["abc"]).await;
+        let (tx, mut rx) = mpsc::channel(1);
+        let blocklist = Blocklist::load(pool.clone(), None).await.unwrap();
+        let store = test_utils::url_store(&pool, tx, blocklist).await;
+
+        // nothing reads the channel, the second click finds it full
+        store.record_click(click("abc", Utc::now()));
+        store.record_click(click("abc", Utc::now()));
+        assert!(rx.recv().await.is_some());
+        assert!(rx.try_recv().is_err());
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+    }
+
+    fn buckets(buckets: &[(&str, i64)]) -> Vec<(String, i64)> {
+        buckets
+            .iter()
+            .map(|(label, count)| (label.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn days_without_clicks_are_filled_in_across_months() {
+        let counts = buckets(&[("2026-02-28", 4)]);
+        let filled = fill_buckets(
+            counts,
+            at("2026-03-01T00:30:00Z"),
+            3,
+            chrono::Duration::days(1),
+            "%Y-%m-%d",
+        );
+        assert_eq!(
+            filled,
+            buckets(&[("2026-02-27", 0), ("2026-02-28", 4), ("2026-03-01", 0)])
+        );
+    }
+
+    #[test]
+    fn hours_without_clicks_are_filled_in_across_days() {
+        let counts = buckets(&[("2026-10-17 23:00", 2), ("2026-10-18 01:00", 1)]);
+        let filled = fill_buckets(
+            counts,
+            at("2026-10-18T01:15:00Z"),
+            4,
+            chrono::Duration::hours(1),
+            "%Y-%m-%d %H:00",
+        );
+        assert_eq!(
+            filled,
+            buckets(&[
+                ("2026-10-17 22:00", 0),
+                ("2026-10-17 23:00", 2),
+                ("2026-10-18 00:00", 0),
+                ("2026-10-18 01:00", 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn counts_outside_the_range_are_left_out() {
+        let counts = buckets(&[("2026-01-01", 9), ("2026-10-18", 1)]);
+        let filled = fill_buckets(
+            counts,
+            at("2026-10-18T12:00:00Z"),
+            2,
+            chrono::Duration::days(1),
+            "%Y-%m-%d",
+        );
+        assert_eq!(filled, buckets(&[("2026-10-17", 0), ("2026-10-18", 1)]));
+    }
+
+    #[tokio::test]
+    async fn stats_of_several_links_add_up() {
+        let pool = pool_with_links(&["abc", "def", "other"]).await;
+        let now = Utc::now();
+        let visit = |shorturl: &str, ip: &str, referrer: Option<&str>| ClickEvent {
+            referrer: referrer.map(ToString::to_string),
+            user_agent: Some(
+                "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0"
+                    .to_string(),
+            ),
+            ip_hash: Some(ip.to_string()),
+            ..click(shorturl, now)
+        };
+        let batch = [
+            visit("abc", "a", Some("https://news.example")),
+            visit("abc", "b", Some("https://news.example")),
+            visit("def", "a", Some("https://news.example")),
+            visit("other", "c", None),
+            // counted in the totals, but too old for the charts
+            ClickEvent {
+                ip_hash: Some("d".to_string()),
+                ..click("def", now - chrono::Duration::days(DAILY_BUCKETS + 5))
+            },
+        ];
+        write_batch(&pool, &batch).await.unwrap();
+
+        let stats = StatsStore::new(pool)
+            .combined_stats(&["abc".to_string(), "def".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(stats.total_clicks, 4);
+        assert_eq!(stats.unique_visitors, 3);
+        assert_eq!(stats.daily.len(), DAILY_BUCKETS as usize);
+        assert_eq!(stats.daily.iter().map(|(_, count)| count).sum::<i64>(), 3);
+        assert_eq!(stats.daily.last().unwrap().1, 3);
+        assert_eq!(stats.hourly.len(), HOURLY_BUCKETS as usize);
+        assert_eq!(stats.hourly.last().unwrap().1, 3);
+        assert_eq!(
+            stats.top_referrers,
+            buckets(&[("https://news.example", 3), ("Direct", 1)])
+        );
+        assert_eq!(stats.browsers, buckets(&[("Firefox", 3), ("UNKNOWN", 1)]));
+        assert_eq!(
+            stats.operating_systems,
+            buckets(&[("Linux", 3), ("UNKNOWN", 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_of_a_single_link_leave_the_others_out() {
+        let pool = pool_with_links(&["abc", "def"]).await;
+        let batch = [
+            click("abc", Utc::now()),
+            click("def", Utc::now()),
+            click("def", Utc::now()),
+        ];
+        write_batch(&pool, &batch).await.unwrap();
+
+        let stats = StatsStore::new(pool).link_stats("def").await.unwrap();
+        assert_eq!(stats.total_clicks, 2);
+        assert_eq!(stats.unique_visitors, 0);
+        assert_eq!(stats.daily.last().unwrap().1, 2);
+    }
+}
@@ -1,69 +1,531 @@
+use async_trait::async_trait;
+use lru::LruCache;
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    fmt::{self, Write},
+    hash::{BuildHasher, RandomState},
+    num::NonZeroUsize,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
-use tokio::{sync::RwLock, task::JoinHandle};
+use tokio::task::JoinHandle;
 
-#[derive(Debug, Clone)]
-struct CacheEntry {
-    value: String,
-    expiry: Instant,
+/// Where lookups are kept between requests. Implemented by the in memory [`TtlCache`], and by
+/// caches shared between instances.
+///
+/// A cache that fails to answer behaves as if the key wasn't cached, it never fails a lookup.
+#[async_trait]
+pub trait CacheBackend<V: Send + 'static>: fmt::Debug + Send + Sync {
+    async fn get(&self, key: &str) -> Option<V>;
+
+    async fn insert(&self, key: String, value: V);
+
+    /// Insert a value that must not be served after `deadline`, regardless of how often it's read
+    async fn insert_until(&self, key: String, value: V, deadline: Instant);
+
+    /// Drop an entry right away, e.g. because the underlying value changed
+    async fn remove(&self, key: &str);
+
+    /// Drop an entry from the memory of this instance only, after another instance changed
+    /// the value and already removed it from anything shared
+    async fn evict_local(&self, key: &str);
+
+    /// Appends the metrics in the prometheus text format, every name starts with `prefix`
+    async fn write_metrics(&self, prefix: &str, out: &mut String);
+}
+
+/// Most shards a cache is split into, small caches get fewer so each shard holds at least one entry
+const MAX_SHARDS: usize = 16;
+
+/// How often, as a fraction of the ttl, a read may move an entry to the front of the lru order.
+/// Reads in between only take the shared lock.
+const PROMOTE_FRACTION: u32 = 8;
+
+#[derive(Debug)]
+struct CacheEntry<V> {
+    value: V,
+    /// Nanos since the cache epoch, atomic so reads can slide it under the shared lock
+    expiry: AtomicU64,
+    /// Hard limit the sliding expiry is never moved past
+    deadline: Option<u64>,
+    /// When a read last moved the entry to the front of the lru order
+    promoted: AtomicU64,
+}
+
+/// One independently locked part of the cache, aligned so the counters of neighbouring
+/// shards don't share a cache line
+#[derive(Debug)]
+#[repr(align(128))]
+struct Shard<V> {
+    map: RwLock<LruCache<String, CacheEntry<V>>>,
+    metrics: CacheMetrics,
 }
 
+/// A cache with a sliding ttl per entry, holding at most `capacity` entries.
+///
+/// Keys are spread over shards that are locked separately. Lookups only take the shared lock of
+/// their shard, so they don't wait on each other. Once a shard is full, inserting drops its least
+/// recently used entry; the order is only updated every so often on reads, so it's approximate.
 #[derive(Clone, Debug)]
-pub struct TtlCache {
-    map: Arc<RwLock<HashMap<String, CacheEntry>>>,
+pub struct TtlCache<V> {
+    shards: Arc<[Shard<V>]>,
+    hasher: RandomState,
+    /// Point in time all expiries are counted from
+    epoch: Instant,
     ttl: Duration,
+    promote_after: u64,
 }
 
-impl TtlCache {
+impl<V: Clone + Send + Sync + 'static> TtlCache<V> {
     /// Create a new cache and start the cleaner immediately
-    pub async fn new(ttl: Duration, cleanup_interval: Duration) -> (Self, JoinHandle<()>) {
+    pub async fn new(
+        ttl: Duration,
+        cleanup_interval: Duration,
+        capacity: NonZeroUsize,
+    ) -> (Self, JoinHandle<()>) {
+        let shard_count = MAX_SHARDS.min(capacity.get());
+        let shard_capacity = NonZeroUsize::new(capacity.get().div_ceil(shard_count))
+            .expect("the capacity is not zero");
+        let shards = (0..shard_count)
+            .map(|_| Shard {
+                map: RwLock::new(LruCache::new(shard_capacity)),
+                metrics: CacheMetrics::default(),
+            })
+            .collect();
         let cache = Self {
-            map: Arc::new(RwLock::new(HashMap::new())),
+            shards,
+            hasher: RandomState::new(),
+            epoch: Instant::now(),
             ttl,
+            promote_after: (ttl / PROMOTE_FRACTION).as_nanos() as u64,
         };
 
         let cleaner = cache._spawn_cleaner(cleanup_interval);
         (cache, cleaner)
     }
 
-    pub async fn insert(&self, key: String, value: String) {
-        let expiry = Instant::now() + self.ttl;
-        let entry = CacheEntry { value, expiry };
-        self.map.write().await.insert(key, entry);
+    fn put(&self, key: String, value: V, deadline: Option<u64>) {
+        let now = self.nanos(Instant::now());
+        let expiry = self.slide(now, deadline);
+        let entry = CacheEntry {
+            value,
+            expiry: AtomicU64::new(expiry),
+            deadline,
+            promoted: AtomicU64::new(now),
+        };
+        let shard = self.shard(&key);
+        let mut map = shard.map.write().expect("cache shard lock is poisoned");
+        // `push` also hands back the old entry when the key was already cached
+        if let Some((evicted, _)) = map.push(key.clone(), entry)
+            && evicted != key
+        {
+            shard.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
-    pub async fn get(&self, key: &str) -> Option<String> {
-        let mut map = self.map.write().await;
-        if let Some(entry) = map.get_mut(key) {
-            let now = Instant::now();
-            if now <= entry.expiry {
-                // sliding TTL: reset expiry
-                entry.expiry = now + self.ttl;
-                return Some(entry.value.clone());
-            } else {
-                map.remove(key);
-            }
+    /// Counters since startup, plus the current size
+    pub async fn metrics(&self) -> CacheMetricsSnapshot {
+        let mut snapshot = CacheMetricsSnapshot::default();
+        for shard in self.shards.iter() {
+            let map = shard.map.read().expect("cache shard lock is poisoned");
+            snapshot.hits += shard.metrics.hits.load(Ordering::Relaxed);
+            snapshot.misses += shard.metrics.misses.load(Ordering::Relaxed);
+            snapshot.evictions += shard.metrics.evictions.load(Ordering::Relaxed);
+            snapshot.expirations += shard.metrics.expirations.load(Ordering::Relaxed);
+            snapshot.entries += map.len();
+            snapshot.capacity += map.cap().get();
         }
-        None
+        snapshot
+    }
+
+    fn shard(&self, key: &str) -> &Shard<V> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    fn nanos(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+
+    fn slide(&self, now: u64, deadline: Option<u64>) -> u64 {
+        let expiry = now + self.ttl.as_nanos() as u64;
+        deadline.map_or(expiry, |deadline| expiry.min(deadline))
     }
 
     /// Internal: spawn the background cleaner
     fn _spawn_cleaner(&self, interval: Duration) -> JoinHandle<()> {
-        let map = self.map.clone();
+        let cache = self.clone();
 
-        let handle = tokio::spawn(async move {
+        tokio::spawn(async move {
             loop {
                 tokio::time::sleep(interval).await;
-                let mut map = map.write().await;
-                let now = Instant::now();
-                map.retain(|_, entry| entry.expiry > now);
-                println!("🧹 Cache cleaned, {} keys remain", map.len());
+                let mut remaining = 0;
+                // one shard at a time, so lookups in the others carry on
+                for shard in cache.shards.iter() {
+                    let mut map = shard.map.write().expect("cache shard lock is poisoned");
+                    let now = cache.nanos(Instant::now());
+                    let expired: Vec<String> = map
+                        .iter()
+                        .filter(|(_, entry)| entry.expiry.load(Ordering::Relaxed) <= now)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in &expired {
+                        map.pop(key);
+                    }
+                    shard
+                        .metrics
+                        .expirations
+                        .fetch_add(expired.len() as u64, Ordering::Relaxed);
+                    remaining += map.len();
+                }
+                tracing::debug!("Cache cleaned, {} keys remain", remaining);
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<V: fmt::Debug + Clone + Send + Sync + 'static> CacheBackend<V> for TtlCache<V> {
+    async fn get(&self, key: &str) -> Option<V> {
+        let shard = self.shard(key);
+        let now = self.nanos(Instant::now());
+        // `Some` if the entry is due to move to the front of the lru order, `None` if it expired
+        let promoted = {
+            let map = shard.map.read().expect("cache shard lock is poisoned");
+            match map.peek(key) {
+                Some(entry) if now < entry.expiry.load(Ordering::Relaxed) => {
+                    // sliding TTL: reset expiry, but never past the deadline
+                    entry
+                        .expiry
+                        .store(self.slide(now, entry.deadline), Ordering::Relaxed);
+                    shard.metrics.hits.fetch_add(1, Ordering::Relaxed);
+                    let value = entry.value.clone();
+                    if now.saturating_sub(entry.promoted.load(Ordering::Relaxed))
+                        < self.promote_after
+                    {
+                        return Some(value);
+                    }
+                    entry.promoted.store(now, Ordering::Relaxed);
+                    Some(value)
+                }
+                Some(_) => None,
+                None => {
+                    shard.metrics.misses.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        };
+
+        let mut map = shard.map.write().expect("cache shard lock is poisoned");
+        match promoted {
+            Some(value) => {
+                map.promote(key);
+                Some(value)
+            }
+            None => {
+                // expired, unless it was replaced since the shared lock was released
+                if map
+                    .peek(key)
+                    .is_some_and(|entry| entry.expiry.load(Ordering::Relaxed) <= now)
+                {
+                    map.pop(key);
+                    shard.metrics.expirations.fetch_add(1, Ordering::Relaxed);
+                }
+                shard.metrics.misses.fetch_add(1, Ordering::Relaxed);
+                None
             }
-        });
+        }
+    }
+
+    async fn insert(&self, key: String, value: V) {
+        self.put(key, value, None);
+    }
+
+    async fn insert_until(&self, key: String, value: V, deadline: Instant) {
+        let deadline = self.nanos(deadline);
+        self.put(key, value, Some(deadline));
+    }
+
+    async fn remove(&self, key: &str) {
+        self.shard(key)
+            .map
+            .write()
+            .expect("cache shard lock is poisoned")
+            .pop(key);
+    }
 
-        handle
+    async fn evict_local(&self, key: &str) {
+        self.remove(key).await;
+    }
+
+    async fn write_metrics(&self, prefix: &str, out: &mut String) {
+        self.metrics().await.write_prometheus(prefix, out);
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Entries dropped to make room, before they expired
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheMetricsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+impl CacheMetricsSnapshot {
+    /// Appends the metrics in the prometheus text format, every name starts with `prefix`
+    pub fn write_prometheus(&self, prefix: &str, out: &mut String) {
+        let metrics = [
+            (
+                "hits_total",
+                "counter",
+                "Lookups answered from the cache",
+                self.hits,
+            ),
+            (
+                "misses_total",
+                "counter",
+                "Lookups that had to go to the database",
+                self.misses,
+            ),
+            (
+                "evictions_total",
+                "counter",
+                "Entries dropped to stay within the capacity",
+                self.evictions,
+            ),
+            (
+                "expirations_total",
+                "counter",
+                "Entries dropped because their ttl ran out",
+                self.expirations,
+            ),
+            (
+                "entries",
+                "gauge",
+                "Entries currently cached",
+                self.entries as u64,
+            ),
+            (
+                "capacity",
+                "gauge",
+                "Most entries the cache holds",
+                self.capacity as u64,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            write_metric(out, prefix, name, kind, help, value);
+        }
+    }
+}
+
+/// Appends one metric with its help and type lines, in the prometheus text format
+pub fn write_metric(
+    out: &mut String,
+    prefix: &str,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: u64,
+) {
+    // writing to a String can't fail
+    let _ = writeln!(out, "# HELP {prefix}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {prefix}_{name} {kind}");
+    let _ = writeln!(out, "{prefix}_{name} {value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    async fn cache(ttl: Duration, capacity: usize) -> TtlCache<u32> {
+        let (cache, cleaner) = TtlCache::new(ttl, HOUR, NonZeroUsize::new(capacity).unwrap()).await;
+        cleaner.abort();
+        cache
+    }
+
+    /// `count` keys that end up in the same shard
+    fn same_shard_keys(cache: &TtlCache<u32>, count: usize) -> Vec<String> {
+        let shard: *const Shard<u32> = cache.shard("key0");
+        (0..)
+            .map(|i| format!("key{i}"))
+            .filter(|key| std::ptr::eq(cache.shard(key), shard))
+            .take(count)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn a_full_shard_drops_its_least_recently_used_entry() {
+        // 16 shards of two entries each
+        let cache = cache(Duration::from_millis(800), 32).await;
+        let keys = same_shard_keys(&cache, 3);
+        cache.insert(keys[0].clone(), 0).await;
+        cache.insert(keys[1].clone(), 1).await;
+        // reads only move entries to the front once an eighth of the ttl passed
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(cache.get(&keys[0]).await, Some(0));
+
+        cache.insert(keys[2].clone(), 2).await;
+        assert_eq!(cache.get(&keys[1]).await, None);
+        assert_eq!(cache.get(&keys[0]).await, Some(0));
+        assert_eq!(cache.get(&keys[2]).await, Some(2));
+        assert_eq!(cache.metrics().await.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn holds_at_most_its_capacity() {
+        let cache = cache(HOUR, 20).await;
+        for i in 0..1000 {
+            cache.insert(format!("key{i}"), i).await;
+        }
+        let metrics = cache.metrics().await;
+        assert!(metrics.capacity >= 20);
+        assert_eq!(metrics.entries, metrics.capacity);
+        assert_eq!(metrics.evictions, 1000 - metrics.capacity as u64);
+    }
+
+    #[tokio::test]
+    async fn replacing_an_entry_is_not_an_eviction() {
+        let cache = cache(HOUR, 1).await;
+        cache.insert("key".to_string(), 1).await;
+        cache.insert("key".to_string(), 2).await;
+        assert_eq!(cache.get("key").await, Some(2));
+        assert_eq!(cache.metrics().await.evictions, 0);
+
+        cache.insert("other".to_string(), 3).await;
+        assert_eq!(cache.get("key").await, None);
+        assert_eq!(cache.metrics().await.evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn reads_slide_the_expiry() {
+        let cache = cache(Duration::from_millis(200), 10).await;
+        cache.insert("key".to_string(), 1).await;
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            assert_eq!(cache.get("key").await, Some(1));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(cache.get("key").await, None);
+
+        let metrics = cache.metrics().await;
+        assert_eq!((metrics.hits, metrics.misses), (3, 1));
+        assert_eq!(metrics.expirations, 1);
+        assert_eq!(metrics.entries, 0);
+    }
+
+    #[tokio::test]
+    async fn reads_never_slide_past_the_deadline() {
+        let cache = cache(HOUR, 10).await;
+        let deadline = Instant::now() + Duration::from_millis(100);
+        cache.insert_until("key".to_string(), 1, deadline).await;
+        assert_eq!(cache.get("key").await, Some(1));
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(cache.get("key").await, None);
+    }
+
+    #[tokio::test]
+    async fn removed_entries_are_gone() {
+        let cache = cache(HOUR, 10).await;
+        cache.insert("key".to_string(), 1).await;
+        cache.remove("key").await;
+        assert_eq!(cache.get("key").await, None);
+        cache.insert("key".to_string(), 1).await;
+        cache.evict_local("key").await;
+        assert_eq!(cache.get("key").await, None);
+    }
+
+    #[tokio::test]
+    async fn the_cleaner_drops_expired_entries() {
+        let (cache, cleaner) = TtlCache::new(
+            Duration::from_millis(50),
+            Duration::from_millis(100),
+            NonZeroUsize::new(10).unwrap(),
+        )
+        .await;
+        cache.insert("key".to_string(), 1_u32).await;
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        cleaner.abort();
+
+        let metrics = cache.metrics().await;
+        assert_eq!(metrics.entries, 0);
+        assert_eq!(metrics.expirations, 1);
+        // nobody asked for it, so it's not a miss
+        assert_eq!(metrics.misses, 0);
+    }
+
+    #[tokio::test]
+    async fn small_caches_get_fewer_shards() {
+        let small = cache(HOUR, 3).await;
+        assert_eq!(small.shards.len(), 3);
+        assert_eq!(small.metrics().await.capacity, 3);
+
+        let large = cache(HOUR, 1000).await;
+        assert_eq!(large.shards.len(), MAX_SHARDS);
+        assert_eq!(
+            large.metrics().await.capacity,
+            MAX_SHARDS * 1000_usize.div_ceil(MAX_SHARDS)
+        );
+    }
+
+    #[tokio::test]
+    async fn lookups_only_take_the_shared_lock() {
+        let cache = cache(HOUR, 10).await;
+        cache.insert("key".to_string(), 1).await;
+        let _shared = cache.shard("key").map.read().unwrap();
+
+        // a lookup needing the write lock would wait for the guard above forever
+        let (tx, rx) = std::sync::mpsc::channel();
+        let reader = cache.clone();
+        let handle = tokio::runtime::Handle::current();
+        std::thread::spawn(move || tx.send(handle.block_on(reader.get("key"))));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(Some(1)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_lookups_all_hit() {
+        let cache = cache(HOUR, 1000).await;
+        for i in 0..100 {
+            cache.insert(format!("key{i}"), i).await;
+        }
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    for i in 0..1000 {
+                        assert_eq!(cache.get(&format!("key{}", i % 100)).await, Some(i % 100));
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        let metrics = cache.metrics().await;
+        assert_eq!((metrics.hits, metrics.misses), (8000, 0));
+    }
+
+    #[test]
+    fn metrics_are_written_in_the_prometheus_format() {
+        let snapshot = CacheMetricsSnapshot {
+            hits: 3,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        snapshot.write_prometheus("test_cache", &mut out);
+        assert!(out.contains("# TYPE test_cache_hits_total counter\ntest_cache_hits_total 3\n"));
+        assert!(out.contains("# TYPE test_cache_entries gauge\ntest_cache_entries 0\n"));
     }
 }
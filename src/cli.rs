@@ -0,0 +1,84 @@
+use clap::{Parser, Subcommand};
+use std::io::{self, BufRead, IsTerminal};
+
+use crate::{hash_pwd, user_store::UserStore};
+
+/// Why a command failed, printed before exiting with a non zero status
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("Unable to read the password: {0}")]
+    Io(#[from] io::Error),
+    #[error("The password can't be empty")]
+    EmptyPassword,
+    #[error("A user with the email {0} already exists")]
+    EmailTaken(String),
+    #[error("Sqlite/Sqlx error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("bcrypt error: {0}")]
+    BcryptError(#[from] bcrypt::BcryptError),
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Yet another url shortner")]
+pub struct Cli {
+    /// Runs the web server when no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a user, the password is prompted for, or read from stdin when it isn't a terminal
+    CreateUser {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+        /// Allow the user to see every link and manage invites
+        #[arg(long)]
+        admin: bool,
+    },
+}
+
+impl Command {
+    pub async fn run(self, users: &UserStore) -> Result<(), CliError> {
+        match self {
+            Command::CreateUser { email, name, admin } => {
+                create_user(users, email, name, admin).await
+            }
+        }
+    }
+}
+
+async fn create_user(
+    users: &UserStore,
+    email: String,
+    name: String,
+    admin: bool,
+) -> Result<(), CliError> {
+    let password = read_password()?;
+    if password.is_empty() {
+        return Err(CliError::EmptyPassword);
+    }
+
+    let hash = hash_pwd(password).await?;
+    let user = users
+        .create_user(&email, &name, &hash, admin)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => CliError::EmailTaken(email),
+            e => e.into(),
+        })?;
+    println!("Created user {} with id {}", user.email, user.id);
+    Ok(())
+}
+
+/// Prompts without echoing what's typed, or reads a line when the password is piped in
+fn read_password() -> io::Result<String> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password("Password: ");
+    }
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
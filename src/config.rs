@@ -0,0 +1,156 @@
+use std::{env, num::NonZeroUsize, path::PathBuf, time::Duration};
+
+use axum_extra::extract::cookie::Key;
+
+use crate::{
+    redirect::RedirectType,
+    shared_cache::CacheBackendKind,
+    short_code::{ShortCodeStrategy, parse_alphabet},
+};
+
+/// Runtime configuration read from the environment (and the `.env` file, if present)
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub database_url: String,
+    /// Address the shortener is reachable at, used to build full short urls
+    pub public_url: String,
+    /// Master key used to sign the session cookie
+    pub session_key: Key,
+    /// How long a login stays valid
+    pub session_ttl: Duration,
+    /// Whether cookies should only be sent over https
+    pub secure_cookies: bool,
+    /// Whether anyone can create an account, invites work regardless of this
+    pub allow_signup: bool,
+    /// How long an invite link stays valid
+    pub invite_ttl: Duration,
+    /// How short codes are generated for links without an alias
+    pub short_code_strategy: ShortCodeStrategy,
+    /// Mixed into visitor ips before hashing them for click stats
+    pub ip_hash_salt: String,
+    /// Take the visitor ip from `X-Forwarded-For`, only enable this behind a reverse proxy
+    pub trust_proxy_headers: bool,
+    /// Url schemes allowed as link destinations besides http and https
+    pub extra_url_schemes: Vec<String>,
+    /// File with blocked domains and patterns, one per line
+    pub blocklist_file: Option<PathBuf>,
+    /// Redirect status for links that don't set their own
+    pub default_redirect: RedirectType,
+    /// Most links kept in the redirect cache, the least recently used are dropped first
+    pub cache_capacity: NonZeroUsize,
+    /// How long a short url that doesn't exist is remembered, creating it ends this early
+    pub missing_cache_ttl: Duration,
+    /// Where the redirect caches are kept
+    pub cache_backend: CacheBackendKind,
+    /// Server for the redis and tiered cache backends
+    pub redis_url: Option<String>,
+    /// Most links the tiered backend keeps in memory, in front of redis
+    pub local_cache_capacity: NonZeroUsize,
+    /// How long the tiered backend keeps a link in memory before asking redis again
+    pub local_cache_ttl: Duration,
+    /// How often the cache invalidation log is checked for changes made by other instances,
+    /// when there is no redis to announce them
+    pub invalidation_poll_interval: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let session_key = match env::var("SESSION_SECRET") {
+            Ok(secret) if secret.len() >= 32 => Key::derive_from(secret.as_bytes()),
+            Ok(_) => panic!("SESSION_SECRET must be at least 32 bytes long"),
+            Err(_) => {
+                tracing::warn!(
+                    "SESSION_SECRET is not set, using a random key. Sessions will not survive a restart."
+                );
+                Key::generate()
+            }
+        };
+
+        let mut short_code_strategy =
+            parse_env("SHORT_CODE_STRATEGY", ShortCodeStrategy::default());
+        match &mut short_code_strategy {
+            ShortCodeStrategy::Nanoid { alphabet, length } => {
+                if let Ok(chars) = env::var("SHORT_CODE_ALPHABET") {
+                    *alphabet = parse_alphabet(&chars)
+                        .unwrap_or_else(|e| panic!("SHORT_CODE_ALPHABET is invalid: {e}"));
+                }
+                *length = parse_env("SHORT_CODE_LENGTH", *length);
+            }
+            ShortCodeStrategy::Hashids { salt, min_length } => {
+                *salt = env::var("SHORT_CODE_SALT").unwrap_or_default();
+                *min_length = parse_env("SHORT_CODE_LENGTH", *min_length);
+            }
+            ShortCodeStrategy::Sequential | ShortCodeStrategy::Words => {}
+        }
+
+        let ip_hash_salt = env::var("IP_HASH_SALT").unwrap_or_else(|_| {
+            tracing::warn!(
+                "IP_HASH_SALT is not set, using a random salt. Unique visitors will be counted again after a restart."
+            );
+            nanoid::nanoid!(32)
+        });
+
+        let cache_backend = parse_env("CACHE_BACKEND", CacheBackendKind::default());
+        let redis_url = env::var("REDIS_URL").ok();
+
+        Self {
+            database_url: env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            public_url: env::var("PUBLIC_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|_| "http://127.0.0.1:3000".to_string()),
+            session_key,
+            session_ttl: Duration::from_secs(parse_env("SESSION_TTL_SECS", 7 * 24 * 60 * 60)),
+            secure_cookies: parse_env("SECURE_COOKIES", false),
+            allow_signup: parse_env("ALLOW_SIGNUP", false),
+            invite_ttl: Duration::from_secs(parse_env("INVITE_TTL_SECS", 7 * 24 * 60 * 60)),
+            short_code_strategy,
+            ip_hash_salt,
+            trust_proxy_headers: parse_env("TRUST_PROXY_HEADERS", false),
+            extra_url_schemes: env::var("EXTRA_URL_SCHEMES")
+                .map(|schemes| {
+                    schemes
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(ToString::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            blocklist_file: env::var("BLOCKLIST_FILE").ok().map(PathBuf::from),
+            default_redirect: parse_env("DEFAULT_REDIRECT_TYPE", RedirectType::Found),
+            cache_capacity: parse_env(
+                "CACHE_MAX_ENTRIES",
+                NonZeroUsize::new(10_000).expect("10000 is not zero"),
+            ),
+            missing_cache_ttl: Duration::from_secs(parse_env("MISSING_CACHE_TTL_SECS", 10)),
+            cache_backend,
+            redis_url,
+            local_cache_capacity: parse_env(
+                "LOCAL_CACHE_MAX_ENTRIES",
+                NonZeroUsize::new(1_000).expect("1000 is not zero"),
+            ),
+            local_cache_ttl: Duration::from_secs(parse_env("LOCAL_CACHE_TTL_SECS", 5)),
+            invalidation_poll_interval: Duration::from_millis(parse_env(
+                "CACHE_INVALIDATION_POLL_MS",
+                1000,
+            )),
+        }
+    }
+}
+
+impl Config {
+    /// The full address of a short url
+    pub fn short_url(&self, shorturl: &str) -> String {
+        format!("{}/{}", self.public_url, shorturl)
+    }
+}
+
+/// Read and parse an environment variable, falling back to `default` when it is unset
+fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} has an invalid value: {value}")),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,152 @@
+use url::Url;
+
+use crate::errors::{AppError, AppResult};
+
+/// Scheme added to destinations typed without one, like `example.com/page`
+const DEFAULT_SCHEME: &str = "https";
+
+/// Decides which destinations a short url may point to, and how they are normalized
+#[derive(Clone, Debug)]
+pub struct DestinationPolicy {
+    /// `http` and `https`, plus whatever was configured in `EXTRA_URL_SCHEMES`
+    allowed_schemes: Vec<String>,
+    /// Host of the shortener itself, links to it would redirect in circles
+    own_host: Option<String>,
+}
+
+impl DestinationPolicy {
+    pub fn new(extra_schemes: &[String], public_url: &str) -> Self {
+        let mut allowed_schemes = vec!["http".to_string(), "https".to_string()];
+        allowed_schemes.extend(extra_schemes.iter().map(|s| s.to_ascii_lowercase()));
+
+        let own_host = Url::parse(public_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if own_host.is_none() {
+            tracing::warn!(
+                "PUBLIC_URL {} has no host, links back to the shortener can't be detected",
+                public_url
+            );
+        }
+
+        Self {
+            allowed_schemes,
+            own_host,
+        }
+    }
+
+    /// Parses a user supplied destination and returns it in normalized form.
+    ///
+    /// Surrounding whitespace is trimmed, a missing scheme defaults to https and
+    /// international domain names are converted to punycode.
+    pub fn normalize(&self, input: &str) -> AppResult<String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(AppError::ValidationError("Please enter a url".to_string()));
+        }
+
+        let url = if has_scheme(input) {
+            Url::parse(input)
+        } else {
+            Url::parse(&format!("{DEFAULT_SCHEME}://{input}"))
+        }
+        .map_err(|e| AppError::ValidationError(format!("This is not a valid url: {e}")))?;
+
+        if !self.allowed_schemes.iter().any(|s| s == url.scheme()) {
+            return Err(AppError::ValidationError(format!(
+                "Links can only use {}",
+                self.allowed_schemes.join(", ")
+            )));
+        }
+
+        if let (Some(own_host), Some(host)) = (&self.own_host, url.host_str())
+            && host.eq_ignore_ascii_case(own_host)
+        {
+            return Err(AppError::ValidationError(
+                "Links can't point back at the shortener itself".to_string(),
+            ));
+        }
+
+        Ok(url.into())
+    }
+}
+
+/// Whether `input` starts with a scheme, `localhost:3000` is a host with a port rather than
+/// a `localhost` scheme
+fn has_scheme(input: &str) -> bool {
+    let Some((scheme, rest)) = input.split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> DestinationPolicy {
+        DestinationPolicy::new(&["ftp".to_string()], "https://sho.rt")
+    }
+
+    fn is_rejected(input: &str) -> bool {
+        matches!(policy().normalize(input), Err(AppError::ValidationError(_)))
+    }
+
+    #[test]
+    fn adds_https_to_destinations_without_a_scheme() {
+        assert_eq!(
+            policy().normalize("  example.com/page ").unwrap(),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            policy().normalize("localhost:3000/x").unwrap(),
+            "https://localhost:3000/x"
+        );
+    }
+
+    #[test]
+    fn keeps_allowed_schemes_and_converts_idns() {
+        assert_eq!(
+            policy().normalize("http://example.com").unwrap(),
+            "http://example.com/"
+        );
+        assert_eq!(
+            policy().normalize("FTP://files.example.com/a").unwrap(),
+            "ftp://files.example.com/a"
+        );
+        assert_eq!(
+            policy().normalize("https://bücher.example").unwrap(),
+            "https://xn--bcher-kva.example/"
+        );
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        assert!(is_rejected("javascript:alert(1)"));
+        assert!(is_rejected("JavaScript:alert(document.cookie)"));
+        assert!(is_rejected("data:text/html,<script>alert(1)</script>"));
+        assert!(is_rejected("mailto:someone@example.com"));
+    }
+
+    #[test]
+    fn rejects_empty_invalid_and_own_destinations() {
+        assert!(is_rejected("   "));
+        assert!(is_rejected("http://"));
+        assert!(is_rejected("https://SHO.RT/abc"));
+        assert!(is_rejected("sho.rt/abc"));
+    }
+
+    #[test]
+    fn tells_schemes_from_ports() {
+        assert!(has_scheme("https://example.com"));
+        assert!(has_scheme("mailto:someone@example.com"));
+        assert!(has_scheme("svn+ssh://example.com"));
+        assert!(!has_scheme("localhost:3000"));
+        assert!(!has_scheme("example.com"));
+        assert!(!has_scheme("1http://example.com"));
+        assert!(!has_scheme(":nothing"));
+    }
+}
@@ -1,15 +1,49 @@
 use axum::{
-    http::StatusCode,
+    extract::{
+        Request,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
     response::{IntoResponse, Response},
 };
 
-use crate::views::ErrorPage;
+use crate::views::{ERROR_SLOT_SELECTOR, ErrorFragment, ErrorPage};
 
 #[derive(Debug, thiserror::Error)]
 //#[non_exhaustive]
 pub enum AppError {
     #[error("Sqlite/Sqlx error: {0}")]
     DatabaseError(#[from] sqlx::Error),
+    #[error("bcrypt error: {0}")]
+    BcryptError(#[from] bcrypt::BcryptError),
+    /// The request was understood, but its input is not acceptable
+    #[error("{0}")]
+    ValidationError(String),
+    /// The input clashes with something that already exists, like a taken alias
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    NotFound(String),
+    /// The thing existed, but can't be used anymore
+    #[error("{0}")]
+    Expired(String),
+    /// The request needs a (valid) login or api token
+    #[error("{0}")]
+    Unauthorized(String),
+    /// Logged in, but not allowed to do this
+    #[error("{0}")]
+    Forbidden(String),
+    // nothing limits requests yet, the variant is here so limits map to the right status
+    #[allow(dead_code)]
+    #[error("{0}")]
+    RateLimited(String),
+    /// Can't be done right now, but trying again later may work
+    #[error("{0}")]
+    ServiceUnavailable(String),
+    #[error("png error: {0}")]
+    PngError(#[from] png::EncodingError),
+    /// Any other status, prefer adding a variant for errors that come up in more than one place
     #[error("{msg}")]
     CustomError { code: StatusCode, msg: String },
 }
@@ -21,21 +55,313 @@ impl AppError {
             msg: msg.to_string(),
         }
     }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::DatabaseError(_) | AppError::BcryptError(_) | AppError::PngError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AppError::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Expired(_) => StatusCode::GONE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::CustomError { code, .. } => *code,
+        }
+    }
+
+    /// The message shown to users, internal errors only end up in the logs
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::DatabaseError(_) => {
+                "Something went wrong, please try again later".to_string()
+            }
+            AppError::BcryptError(_) => "Unable to verify credentials".to_string(),
+            AppError::PngError(_) => "Unable to render the image".to_string(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Render the error as an rfc 9457 problem details json body
+    pub fn problem_details(&self) -> Response {
+        ErrorDetails::from(self).problem_details()
+    }
 }
 
 impl IntoResponse for AppError {
+    /// Renders the full error page, [`negotiate_error_format`] swaps it for json or an htmx
+    /// fragment depending on the request
     fn into_response(self) -> axum::response::Response {
-        tracing::error!("Error occurred: {}", self);
-        let status = match self {
-            AppError::DatabaseError(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            AppError::CustomError { code, .. } => code,
-            // _ => StatusCode::INTERNAL_SERVER_ERROR
-        };
-        ErrorPage::new()
-            .set_status(status)
-            .set_message(self.to_string())
+        log_error(&self);
+        let details = ErrorDetails::from(&self);
+        let mut response = ErrorPage::new()
+            .set_status(details.status)
+            .set_message(&details.message)
+            .into_response();
+        response.extensions_mut().insert(details);
+        response
+    }
+}
+
+fn log_error(e: &AppError) {
+    if e.status().is_server_error() {
+        tracing::error!("Error occurred: {}", e);
+    } else {
+        tracing::debug!("Request failed: {}", e);
+    }
+}
+
+/// Status and message of an [`AppError`], kept in the response extensions so the
+/// error can be rendered again once the request headers are known
+#[derive(Debug, Clone)]
+struct ErrorDetails {
+    status: StatusCode,
+    message: String,
+}
+
+impl From<&AppError> for ErrorDetails {
+    fn from(e: &AppError) -> Self {
+        Self {
+            status: e.status(),
+            message: e.public_message(),
+        }
+    }
+}
+
+impl ErrorDetails {
+    fn problem_details(&self) -> Response {
+        let body = serde_json::json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason().unwrap_or("Error"),
+            "status": self.status.as_u16(),
+            "detail": self.message,
+        });
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body.to_string(),
+        )
             .into_response()
     }
+
+    fn fragment(&self) -> Response {
+        // whatever the request targeted, the error goes into the slot every page has
+        let headers = [
+            ("HX-Retarget", ERROR_SLOT_SELECTOR),
+            ("HX-Reswap", "innerHTML"),
+        ];
+        (self.status, headers, ErrorFragment::new(&self.message)).into_response()
+    }
 }
 
-pub type AppResult = Result<Response, AppError>;
+/// How the client wants to see errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Page,
+    Fragment,
+    Json,
+}
+
+impl ErrorFormat {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        if headers.contains_key("Hx-Request") {
+            return Self::Fragment;
+        }
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        // browsers always ask for html, only clients explicitly asking for json get it
+        if accept.contains("text/html") {
+            Self::Page
+        } else if accept.contains("application/json") || accept.contains("application/problem+json")
+        {
+            Self::Json
+        } else {
+            Self::Page
+        }
+    }
+}
+
+/// Middleware rendering [`AppError`]s as json or an htmx fragment instead of the full page,
+/// based on the `Accept` and `Hx-Request` headers of the request
+pub async fn negotiate_error_format(request: Request, next: Next) -> Response {
+    let format = ErrorFormat::from_headers(request.headers());
+    let mut response = next.run(request).await;
+    let Some(details) = response.extensions_mut().remove::<ErrorDetails>() else {
+        return response;
+    };
+    match format {
+        ErrorFormat::Page => response,
+        ErrorFormat::Fragment => details.fragment(),
+        ErrorFormat::Json => details.problem_details(),
+    }
+}
+
+pub type AppResult<T = Response> = Result<T, AppError>;
+
+/// An [`AppError`] rendered as json, used by the api handlers
+#[derive(Debug)]
+pub struct ApiError(pub AppError);
+
+impl<E> From<E> for ApiError
+where
+    E: Into<AppError>,
+{
+    fn from(e: E) -> Self {
+        Self(e.into())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::custom(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::ValidationError(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::custom(rejection.status(), rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        log_error(&self.0);
+        self.0.problem_details()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::get};
+    use tower::ServiceExt;
+
+    /// The response to `headers` of a route failing with a 404, behind the negotiation
+    async fn failing_request(headers: &[(&str, &str)]) -> (Response, String) {
+        let router = Router::new()
+            .route(
+                "/",
+                get(async || -> AppResult { Err(AppError::NotFound("Url not found".to_string())) }),
+            )
+            .layer(axum::middleware::from_fn(negotiate_error_format));
+        let mut request = axum::http::Request::get("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(std::mem::take(response.body_mut()), usize::MAX)
+            .await
+            .unwrap();
+        (response, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn variants_map_to_their_status() {
+        let cases = [
+            (
+                AppError::ValidationError(String::new()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (AppError::Conflict(String::new()), StatusCode::CONFLICT),
+            (AppError::NotFound(String::new()), StatusCode::NOT_FOUND),
+            (AppError::Expired(String::new()), StatusCode::GONE),
+            (
+                AppError::Unauthorized(String::new()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (AppError::Forbidden(String::new()), StatusCode::FORBIDDEN),
+            (
+                AppError::RateLimited(String::new()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                AppError::ServiceUnavailable(String::new()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                AppError::DatabaseError(sqlx::Error::RowNotFound),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status(), status, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn internal_errors_are_not_shown_to_users() {
+        let error = AppError::DatabaseError(sqlx::Error::RowNotFound);
+        assert!(!error.public_message().contains("no rows"));
+        let error = AppError::Conflict("The alias \"abc\" is taken".to_string());
+        assert_eq!(error.public_message(), "The alias \"abc\" is taken");
+    }
+
+    #[tokio::test]
+    async fn problem_details_are_json() {
+        let response = AppError::NotFound("Url not found".to_string()).problem_details();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("a body");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["detail"], "Url not found");
+    }
+
+    #[tokio::test]
+    async fn htmx_requests_get_a_fragment_for_the_error_slot() {
+        let (response, body) =
+            failing_request(&[("HX-Request", "true"), ("Accept", "text/html")]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["HX-Retarget"], ERROR_SLOT_SELECTOR);
+        assert_eq!(response.headers()["HX-Reswap"], "innerHTML");
+        assert!(body.contains("Url not found"));
+        assert!(!body.contains("<html"));
+    }
+
+    #[tokio::test]
+    async fn json_clients_get_problem_details() {
+        for accept in ["application/json", "application/problem+json"] {
+            let (response, body) = failing_request(&[("Accept", accept)]).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/problem+json"
+            );
+            let body: serde_json::Value = serde_json::from_str(&body).expect("json");
+            assert_eq!(body["detail"], "Url not found");
+        }
+    }
+
+    #[tokio::test]
+    async fn everyone_else_gets_the_error_page() {
+        let browser = [("Accept", "text/html,application/json;q=0.9")];
+        for headers in [&browser[..], &[], &[("Accept", "*/*")]] {
+            let (response, body) = failing_request(headers).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert!(response.headers().get("HX-Retarget").is_none());
+            assert!(body.contains("<html"), "{headers:?}");
+            assert!(body.contains("Url not found"));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use axum::{
+    extract::{FromRef, FromRequest, FromRequestParts},
+    http::{HeaderValue, StatusCode, Uri, header, request::Parts},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{SignedCookieJar, cookie::Key};
+use std::convert::Infallible;
+
+use crate::{
+    errors::{ApiError, AppError, AppResult},
+    handlers::auth::SESSION_COOKIE,
+    user_store::{TokenScope, User, UserStore},
+};
+
+pub struct HxRequest(pub bool);
+
+impl<S> FromRequestParts<S> for HxRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // If the "Hx-Request" header is present, the value is always "true" as per https://htmx.org/reference/#request_headers
+        Ok(Self(parts.headers.contains_key("Hx-Request")))
+    }
+}
+
+/// `axum::Json`, but a malformed body is rejected with a json error
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// The user owning the session cookie sent with the request.
+///
+/// Using this extractor in a handler makes the route require a login,
+/// unauthenticated requests are sent to the login page instead.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub User);
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+    UserStore: FromRef<S>,
+    Key: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match session_user(parts, state).await {
+            Ok(Some(user)) => Ok(Self(user)),
+            Ok(None) => Err(login_redirect(parts)),
+            Err(e) => Err(e.into_response()),
+        }
+    }
+}
+
+/// The user calling the json api.
+///
+/// Requests authenticate with an `Authorization: Bearer` api token, or fall back to the
+/// session cookie so the api can be used from the browser. Unlike [`CurrentUser`],
+/// unauthenticated requests get a json 401 instead of a redirect.
+#[derive(Debug, Clone)]
+pub struct ApiUser {
+    pub user: User,
+    /// Scope of the token used, sessions can do everything
+    pub scope: TokenScope,
+}
+
+impl ApiUser {
+    /// Fails with 403 if the request was made with a token lacking `scope`
+    pub fn require(&self, scope: TokenScope) -> Result<(), ApiError> {
+        if self.scope >= scope {
+            Ok(())
+        } else {
+            Err(
+                AppError::Forbidden(format!("This token needs the {} scope", scope.as_str()))
+                    .into(),
+            )
+        }
+    }
+
+    /// Whether the links of other users can be reached, only admins can and only with a token
+    /// that has the admin scope, so a token for their own links can't touch anyone else's
+    pub fn reaches_all_links(&self) -> bool {
+        self.user.is_admin && self.scope >= TokenScope::Admin
+    }
+}
+
+impl<S> FromRequestParts<S> for ApiUser
+where
+    S: Send + Sync,
+    UserStore: FromRef<S>,
+    Key: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(authorization) = parts.headers.get(header::AUTHORIZATION) {
+            let token = authorization
+                .to_str()
+                .ok()
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .ok_or_else(|| {
+                    AppError::Unauthorized(
+                        "Expected an `Authorization: Bearer <token>` header".to_string(),
+                    )
+                })?;
+            return match UserStore::from_ref(state)
+                .get_token_user(token.trim())
+                .await?
+            {
+                Some((user, scope)) => Ok(Self { user, scope }),
+                None => Err(AppError::Unauthorized("Invalid api token".to_string()).into()),
+            };
+        }
+
+        match session_user(parts, state).await? {
+            Some(user) => Ok(Self {
+                user,
+                scope: TokenScope::Admin,
+            }),
+            None => Err(AppError::Unauthorized("Authentication required".to_string()).into()),
+        }
+    }
+}
+
+/// Looks up the user of the session cookie, `None` if there is no valid session
+async fn session_user<S>(parts: &mut Parts, state: &S) -> AppResult<Option<User>>
+where
+    S: Send + Sync,
+    UserStore: FromRef<S>,
+    Key: FromRef<S>,
+{
+    let jar = SignedCookieJar::<Key>::from_request_parts(parts, state)
+        .await
+        .unwrap_or_else(|never| match never {});
+
+    let Some(cookie) = jar.get(SESSION_COOKIE) else {
+        return Ok(None);
+    };
+    let user = UserStore::from_ref(state)
+        .get_session_user(cookie.value())
+        .await?;
+    if user.is_none() {
+        tracing::debug!("Session is unknown or expired");
+    }
+    Ok(user)
+}
+
+/// Send the client to the login page, remembering where it wanted to go.
+///
+/// htmx requests get an `HX-Redirect` header so the whole page navigates,
+/// instead of the login page being swapped into the target element.
+fn login_redirect(parts: &Parts) -> Response {
+    let is_hx = parts.headers.contains_key("Hx-Request");
+
+    let redirect_to = if is_hx {
+        // For htmx requests the interesting location is the page the user is on,
+        // not the endpoint the fragment was requested from
+        parts
+            .headers
+            .get("Hx-Current-URL")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Uri>().ok())
+            .and_then(|uri| uri.path_and_query().map(|pq| pq.to_string()))
+            .unwrap_or_else(|| "/".to_string())
+    } else {
+        parts
+            .uri
+            .path_and_query()
+            .map(|pq| pq.to_string())
+            .unwrap_or_else(|| "/".to_string())
+    };
+
+    let query = serde_urlencoded::to_string([("redirect_to", redirect_to)])
+        .expect("a single string pair is always encodable");
+    let location = format!("/login?{query}");
+
+    if is_hx {
+        let mut response = StatusCode::UNAUTHORIZED.into_response();
+        response.headers_mut().insert(
+            "HX-Redirect",
+            HeaderValue::from_str(&location).expect("urlencoded value is a valid header"),
+        );
+        response
+    } else {
+        Redirect::to(&location).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_user(is_admin: bool, scope: TokenScope) -> ApiUser {
+        ApiUser {
+            user: User {
+                id: 1,
+                email: "someone@example.com".to_string(),
+                name: "Someone".to_string(),
+                is_admin,
+            },
+            scope,
+        }
+    }
+
+    #[test]
+    fn scopes_include_the_ones_before_them() {
+        let manage = api_user(false, TokenScope::Manage);
+        assert!(manage.require(TokenScope::Read).is_ok());
+        assert!(manage.require(TokenScope::Create).is_ok());
+        assert!(manage.require(TokenScope::Manage).is_ok());
+        assert!(manage.require(TokenScope::Admin).is_err());
+        assert!(
+            api_user(false, TokenScope::Create)
+                .require(TokenScope::Manage)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn only_admin_tokens_of_admins_reach_all_links() {
+        assert!(api_user(true, TokenScope::Admin).reaches_all_links());
+        assert!(!api_user(true, TokenScope::Manage).reaches_all_links());
+        assert!(!api_user(true, TokenScope::Read).reaches_all_links());
+        assert!(!api_user(false, TokenScope::Admin).reaches_all_links());
+    }
+}
@@ -0,0 +1,202 @@
+use axum::{
+    Form,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    errors::{ApiResult, AppError, AppResult},
+    extractors::{ApiUser, CurrentUser},
+    moderation::{Blocklist, ModerationStore},
+    url_store::UrlStore,
+    user_store::{TokenScope, User, UserStore},
+    views::{BlocklistPage, DashboardPageBuilder, InvitesPage, ReportsPage},
+};
+
+/// Same as the dashboard, but lists the links of every user
+pub async fn get_admin_links(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+) -> AppResult {
+    require_admin(&user)?;
+    let values = u.get_all_owners().await?;
+    let page = DashboardPageBuilder::new()
+        .set_user(user)
+        .set_rows(values)
+        .set_title("All links");
+
+    Ok((StatusCode::OK, page).into_response())
+}
+
+pub async fn get_invites(
+    CurrentUser(user): CurrentUser,
+    State(users): State<UserStore>,
+) -> AppResult {
+    require_admin(&user)?;
+    let invites = users.get_pending_invites().await?;
+    Ok(InvitesPage::new(user, invites).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateInviteForm {
+    email: Option<String>,
+}
+
+pub async fn post_invite(
+    CurrentUser(user): CurrentUser,
+    State(users): State<UserStore>,
+    State(config): State<Config>,
+    Form(CreateInviteForm { email }): Form<CreateInviteForm>,
+) -> AppResult {
+    require_admin(&user)?;
+    // browsers send empty inputs as empty strings
+    let email = email.as_deref().map(str::trim).filter(|e| !e.is_empty());
+    let invite = users
+        .create_invite(user.id, email, config.invite_ttl)
+        .await?;
+    tracing::info!(admin_id = user.id, expires_at = %invite.expires_at, "Invite created");
+    Ok(Redirect::to("/admin/invites").into_response())
+}
+
+pub async fn get_reports(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    State(moderation): State<ModerationStore>,
+) -> AppResult {
+    require_admin(&user)?;
+    let reports = moderation.open_reports().await?;
+    let disabled = u.get_disabled().await?;
+    Ok(ReportsPage::new(user, reports, disabled).into_response())
+}
+
+/// The reports were looked at and the link is fine
+pub async fn post_dismiss_reports(
+    CurrentUser(user): CurrentUser,
+    State(moderation): State<ModerationStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    require_admin(&user)?;
+    moderation.resolve_reports(&s).await?;
+    tracing::info!(admin_id = user.id, shorturl = %s, "Reports dismissed");
+    Ok(Redirect::to("/admin/reports").into_response())
+}
+
+pub async fn post_disable_link(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    State(moderation): State<ModerationStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    require_admin(&user)?;
+    u.set_disabled(&s, true).await?;
+    moderation.resolve_reports(&s).await?;
+    tracing::info!(admin_id = user.id, shorturl = %s, "Link disabled");
+    Ok(Redirect::to("/admin/reports").into_response())
+}
+
+pub async fn post_enable_link(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    require_admin(&user)?;
+    u.set_disabled(&s, false).await?;
+    tracing::info!(admin_id = user.id, shorturl = %s, "Link enabled");
+    Ok(Redirect::to("/admin/reports").into_response())
+}
+
+pub async fn get_blocklist(
+    CurrentUser(user): CurrentUser,
+    State(blocklist): State<Blocklist>,
+) -> AppResult {
+    require_admin(&user)?;
+    let entries = blocklist.entries().await?;
+    Ok(BlocklistPage::new(user, blocklist.file_patterns().to_vec(), entries).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlocklistForm {
+    pattern: String,
+}
+
+pub async fn post_blocklist(
+    CurrentUser(user): CurrentUser,
+    State(blocklist): State<Blocklist>,
+    Form(BlocklistForm { pattern }): Form<BlocklistForm>,
+) -> AppResult {
+    require_admin(&user)?;
+    blocklist.add(&pattern, user.id).await?;
+    tracing::info!(admin_id = user.id, pattern = %pattern, "Added to the blocklist");
+    Ok(Redirect::to("/admin/blocklist").into_response())
+}
+
+pub async fn post_delete_blocklist_entry(
+    CurrentUser(user): CurrentUser,
+    State(blocklist): State<Blocklist>,
+    Path(id): Path<i64>,
+) -> AppResult {
+    require_admin(&user)?;
+    blocklist.remove(id).await?;
+    tracing::info!(
+        admin_id = user.id,
+        entry_id = id,
+        "Removed from the blocklist"
+    );
+    Ok(Redirect::to("/admin/blocklist").into_response())
+}
+
+/// Counters in the prometheus text format, scrapers can use an api token of an admin
+pub async fn get_metrics(api_user: ApiUser, State(u): State<UrlStore>) -> ApiResult<Response> {
+    api_user.require(TokenScope::Read)?;
+    require_admin(&api_user.user)?;
+    let mut body = String::new();
+    u.write_cache_metrics(&mut body).await;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response())
+}
+
+fn require_admin(user: &User) -> AppResult<()> {
+    if user.is_admin {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden("Only admins can do this".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn only_admins_see_the_links_of_every_user() {
+        let pool = test_utils::pool().await;
+        let state = test_utils::state(&pool).await;
+        let admin = test_utils::user(&pool, "admin@example.com", true).await;
+        let alice = test_utils::user(&pool, "alice@example.com", false).await;
+        test_utils::links(&pool, admin.id, &["adminlink"]).await;
+        test_utils::links(&pool, alice.id, &["alicelink"]).await;
+
+        let request = |cookie: String| {
+            Request::get("/admin/links")
+                .header("Cookie", cookie)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let cookie = test_utils::session_cookie(&state, admin.id).await;
+        let response = test_utils::send(&state, request(cookie)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = test_utils::text_body(response).await;
+        assert!(page.contains("adminlink"));
+        assert!(page.contains("alicelink"));
+
+        let cookie = test_utils::session_cookie(&state, alice.id).await;
+        let response = test_utils::send(&state, request(cookie)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
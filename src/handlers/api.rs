@@ -0,0 +1,473 @@
+//! Versioned json api under `/api/v1`, errors are returned as problem details
+
+use axum::{
+    Json,
+    extract::{
+        Path, Query, State,
+        rejection::{PathRejection, QueryRejection},
+    },
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    errors::ApiResult,
+    extractors::{ApiJson, ApiUser},
+    handlers::links::get_reachable_row,
+    redirect::RedirectType,
+    serde_utils::double_option,
+    url_store::{LinkChanges, NewShortUrl, ShortUrlRow, UrlStore},
+    user_store::TokenScope,
+    utm::UtmParams,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateLinkRequest {
+    url: String,
+    alias: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i64>,
+    redirect_type: Option<RedirectType>,
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(flatten)]
+    utm: UtmParams,
+}
+
+/// Fields left out stay as they are, `"redirect_type": null` goes back to the server default
+/// and an empty string clears a utm parameter
+#[derive(Debug, Deserialize)]
+pub struct UpdateLinkRequest {
+    url: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    redirect_type: Option<Option<RedirectType>>,
+    passthrough: Option<bool>,
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    utm_term: Option<String>,
+    utm_content: Option<String>,
+}
+
+/// A link as returned by the api, the row plus the full short url
+#[derive(Debug, Serialize)]
+pub struct LinkResponse {
+    #[serde(flatten)]
+    row: ShortUrlRow,
+    short_url: String,
+}
+
+impl LinkResponse {
+    fn new(row: ShortUrlRow, config: &Config) -> Self {
+        Self {
+            short_url: config.short_url(&row.shorturl),
+            row,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkListResponse {
+    links: Vec<LinkResponse>,
+}
+
+#[tracing::instrument(skip_all, fields(user_id = api_user.user.id))]
+pub async fn create_link(
+    api_user: ApiUser,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    ApiJson(req): ApiJson<CreateLinkRequest>,
+) -> ApiResult<Response> {
+    api_user.require(TokenScope::Create)?;
+    let new = NewShortUrl {
+        longurl: req.url,
+        alias: req.alias,
+        expires_at: req.expires_at,
+        max_clicks: req.max_clicks,
+        redirect_type: req.redirect_type,
+        passthrough: req.passthrough,
+        utm: req.utm,
+    };
+    let row = u.insert(api_user.user.id, new).await?;
+    tracing::info!("Created short url {}", row.shorturl);
+
+    let link = LinkResponse::new(row, &config);
+    let location = format!("/api/v1/links/{}", link.row.shorturl);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(link),
+    )
+        .into_response())
+}
+
+pub async fn list_links(
+    ApiUser { user, .. }: ApiUser,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    filter: Result<Query<UtmParams>, QueryRejection>,
+) -> ApiResult<Json<LinkListResponse>> {
+    let Query(filter) = filter?;
+    let links = u
+        .get_all(user.id, &filter)
+        .await?
+        .into_iter()
+        .map(|row| LinkResponse::new(row, &config))
+        .collect();
+    Ok(Json(LinkListResponse { links }))
+}
+
+pub async fn get_link(
+    api_user: ApiUser,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    path: Result<Path<String>, PathRejection>,
+) -> ApiResult<Json<LinkResponse>> {
+    let Path(s) = path?;
+    let row = get_api_row(&u, &api_user, &s).await?;
+    Ok(Json(LinkResponse::new(row, &config)))
+}
+
+#[tracing::instrument(skip(u, api_user, config, req), fields(user_id = api_user.user.id))]
+pub async fn update_link(
+    api_user: ApiUser,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    path: Result<Path<String>, PathRejection>,
+    ApiJson(req): ApiJson<UpdateLinkRequest>,
+) -> ApiResult<Json<LinkResponse>> {
+    let Path(s) = path?;
+    api_user.require(TokenScope::Manage)?;
+    get_api_row(&u, &api_user, &s).await?;
+    let changes = LinkChanges {
+        longurl: req.url,
+        redirect_type: req.redirect_type,
+        passthrough: req.passthrough,
+        utm: UtmParams {
+            utm_source: req.utm_source,
+            utm_medium: req.utm_medium,
+            utm_campaign: req.utm_campaign,
+            utm_term: req.utm_term,
+            utm_content: req.utm_content,
+        },
+    };
+    let row = u.update(&s, changes).await?;
+    tracing::info!("Short url updated");
+    Ok(Json(LinkResponse::new(row, &config)))
+}
+
+#[tracing::instrument(skip(u, api_user), fields(user_id = api_user.user.id))]
+pub async fn delete_link(
+    api_user: ApiUser,
+    State(u): State<UrlStore>,
+    path: Result<Path<String>, PathRejection>,
+) -> ApiResult<StatusCode> {
+    let Path(s) = path?;
+    api_user.require(TokenScope::Manage)?;
+    get_api_row(&u, &api_user, &s).await?;
+    u.delete(&s).await?;
+    tracing::info!("Short url deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Loads a row the token is allowed to see and change
+async fn get_api_row(u: &UrlStore, api_user: &ApiUser, s: &str) -> ApiResult<ShortUrlRow> {
+    Ok(get_reachable_row(u, api_user.user.id, api_user.reaches_all_links(), s).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppState, test_utils, user_store::UserStore};
+    use axum::{body::Body, http::Request};
+    use serde_json::{Value, json};
+
+    /// A server with the users alice and bob, and tokens of alice with every scope
+    struct Api {
+        state: AppState,
+        alice: TokenSet,
+        bob: String,
+    }
+
+    struct TokenSet {
+        read: String,
+        create: String,
+        manage: String,
+    }
+
+    impl Api {
+        async fn new() -> Self {
+            let pool = test_utils::pool().await;
+            let state = test_utils::state(&pool).await;
+            let alice = test_utils::user(&pool, "alice@example.com", false).await;
+            let bob = test_utils::user(&pool, "bob@example.com", false).await;
+            let users = UserStore::new(pool, std::time::Duration::from_secs(60));
+            let token = async |user_id, scope| {
+                users
+                    .create_api_token(user_id, "test", scope)
+                    .await
+                    .unwrap()
+                    .1
+            };
+            Self {
+                alice: TokenSet {
+                    read: token(alice.id, TokenScope::Read).await,
+                    create: token(alice.id, TokenScope::Create).await,
+                    manage: token(alice.id, TokenScope::Manage).await,
+                },
+                bob: token(bob.id, TokenScope::Manage).await,
+                state,
+            }
+        }
+
+        async fn send(
+            &self,
+            method: &str,
+            path: &str,
+            token: &str,
+            body: Option<Value>,
+        ) -> (StatusCode, axum::http::HeaderMap, Value) {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::AUTHORIZATION, format!("Bearer {token}"));
+            let request = match body {
+                Some(body) => request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            };
+            let response = test_utils::send(&self.state, request.unwrap()).await;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = if status == StatusCode::NO_CONTENT {
+                Value::Null
+            } else {
+                test_utils::json_body(response).await
+            };
+            (status, headers, body)
+        }
+
+        async fn create(&self, body: Value) -> Value {
+            let (status, _, link) = self
+                .send("POST", "/api/v1/links", &self.alice.create, Some(body))
+                .await;
+            assert_eq!(status, StatusCode::CREATED, "{link}");
+            link
+        }
+    }
+
+    #[tokio::test]
+    async fn created_links_are_returned_with_their_location() {
+        let api = Api::new().await;
+        let (status, headers, link) = api
+            .send(
+                "POST",
+                "/api/v1/links",
+                &api.alice.create,
+                Some(json!({"url": "https://example.com/a", "alias": "abc"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(headers[header::LOCATION], "/api/v1/links/abc");
+        assert_eq!(link["shorturl"], "abc");
+        assert_eq!(link["longurl"], "https://example.com/a");
+        assert_eq!(link["short_url"], "https://sho.rt/abc");
+
+        let (status, _, fetched) = api
+            .send("GET", "/api/v1/links/abc", &api.alice.read, None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fetched, link);
+    }
+
+    #[tokio::test]
+    async fn taken_aliases_are_a_conflict() {
+        let api = Api::new().await;
+        api.create(json!({"url": "https://example.com/a", "alias": "abc"}))
+            .await;
+        let (status, headers, problem) = api
+            .send(
+                "POST",
+                "/api/v1/links",
+                &api.bob,
+                Some(json!({"url": "https://example.com/b", "alias": "abc"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(problem["status"], 409);
+        assert!(problem["detail"].as_str().unwrap().contains("abc"));
+    }
+
+    #[tokio::test]
+    async fn links_are_listed_by_campaign() {
+        let api = Api::new().await;
+        api.create(
+            json!({"url": "https://example.com/a", "alias": "spring", "utm_campaign": "spring"}),
+        )
+        .await;
+        api.create(json!({"url": "https://example.com/b", "alias": "other"}))
+            .await;
+
+        let (status, _, list) = api
+            .send("GET", "/api/v1/links", &api.alice.read, None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list["links"].as_array().unwrap().len(), 2);
+
+        let (status, _, list) = api
+            .send(
+                "GET",
+                "/api/v1/links?utm_campaign=spring",
+                &api.alice.read,
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let links = list["links"].as_array().unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0]["shorturl"], "spring");
+    }
+
+    #[tokio::test]
+    async fn updates_leave_out_fields_alone_and_clear_null_and_empty_ones() {
+        let api = Api::new().await;
+        api.create(json!({
+            "url": "https://example.com/a",
+            "alias": "abc",
+            "redirect_type": 301,
+            "utm_source": "news",
+            "utm_medium": "email",
+        }))
+        .await;
+
+        let (status, _, link) = api
+            .send(
+                "PATCH",
+                "/api/v1/links/abc",
+                &api.alice.manage,
+                Some(json!({"url": "https://example.com/b"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(link["longurl"], "https://example.com/b");
+        assert_eq!(link["redirect_type"], 301);
+        assert_eq!(link["utm_source"], "news");
+
+        let (status, _, link) = api
+            .send(
+                "PATCH",
+                "/api/v1/links/abc",
+                &api.alice.manage,
+                Some(json!({"redirect_type": null, "utm_source": ""})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(link["longurl"], "https://example.com/b");
+        assert_eq!(link["redirect_type"], Value::Null);
+        assert_eq!(link["utm_source"], Value::Null);
+        assert_eq!(link["utm_medium"], "email");
+    }
+
+    #[tokio::test]
+    async fn deleted_links_are_gone() {
+        let api = Api::new().await;
+        api.create(json!({"url": "https://example.com/a", "alias": "abc"}))
+            .await;
+        let (status, _, _) = api
+            .send("DELETE", "/api/v1/links/abc", &api.alice.manage, None)
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = api
+            .send("GET", "/api/v1/links/abc", &api.alice.read, None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn links_of_other_users_are_not_found() {
+        let api = Api::new().await;
+        api.create(json!({"url": "https://example.com/a", "alias": "abc"}))
+            .await;
+
+        let update = Some(json!({"url": "https://example.com/evil"}));
+        for (method, body) in [("GET", None), ("PATCH", update), ("DELETE", None)] {
+            let (status, headers, problem) =
+                api.send(method, "/api/v1/links/abc", &api.bob, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{method}");
+            assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+            assert_eq!(problem["status"], 404);
+        }
+        let (_, _, list) = api.send("GET", "/api/v1/links", &api.bob, None).await;
+        assert_eq!(list["links"], json!([]));
+
+        let (_, _, link) = api
+            .send("GET", "/api/v1/links/abc", &api.alice.read, None)
+            .await;
+        assert_eq!(link["longurl"], "https://example.com/a");
+    }
+
+    #[tokio::test]
+    async fn tokens_are_limited_to_their_scope() {
+        let api = Api::new().await;
+        let (status, headers, problem) = api
+            .send(
+                "POST",
+                "/api/v1/links",
+                &api.alice.read,
+                Some(json!({"url": "https://example.com/a", "alias": "abc"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(problem["detail"], "This token needs the create scope");
+
+        api.create(json!({"url": "https://example.com/a", "alias": "abc"}))
+            .await;
+        let (status, _, _) = api
+            .send(
+                "PATCH",
+                "/api/v1/links/abc",
+                &api.alice.create,
+                Some(json!({"url": "https://example.com/b"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = api
+            .send("DELETE", "/api/v1/links/abc", &api.alice.create, None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_valid_token_are_unauthorized() {
+        let api = Api::new().await;
+        let (status, headers, problem) = api
+            .send("GET", "/api/v1/links", "yaus_not_a_token", None)
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(problem["status"], 401);
+        assert_eq!(problem["detail"], "Invalid api token");
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_are_problems() {
+        let api = Api::new().await;
+        let (status, headers, problem) = api
+            .send(
+                "POST",
+                "/api/v1/links",
+                &api.alice.create,
+                Some(json!({"alias": "abc"})),
+            )
+            .await;
+        assert!(status.is_client_error());
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+        assert_eq!(problem["status"], status.as_u16());
+    }
+}
@@ -0,0 +1,230 @@
+use axum::{
+    Form,
+    extract::{Query, State},
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    SignedCookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+
+use crate::{
+    compare_pwd,
+    config::Config,
+    errors::{AppError, AppResult},
+    hash_pwd,
+    user_store::{Invite, Session, User, UserStore},
+    views::{
+        LoginFormPage, LoginFormPayload, MIN_PASSWORD_LENGTH, SignupFormPage, SignupFormPayload,
+    },
+};
+
+/// Name of the cookie holding the (signed) session id
+pub const SESSION_COOKIE: &str = "session";
+
+/// Checked against when the email is unknown, so a failed login takes as long whether or not
+/// the account exists. Hashed with the same cost as real passwords.
+const DUMMY_HASH: &str = "$2b$10$VG.PFHn00uuUjvulx85QEeX8YGCP.UaZbIxh1HeIOKYExg1lWDhoK";
+
+#[derive(Deserialize, Debug)]
+pub struct LoginPageQueryParams {
+    redirect_to: Option<String>,
+}
+
+#[tracing::instrument(skip(config))]
+pub async fn get_login(
+    State(config): State<Config>,
+    Query(LoginPageQueryParams { redirect_to }): Query<LoginPageQueryParams>,
+) -> AppResult {
+    Ok(LoginFormPage::new()
+        .maybe_redirect_to(redirect_to)
+        .show_signup_link(config.allow_signup)
+        .into_response())
+}
+
+#[tracing::instrument(skip_all, fields(email = %data.email))]
+pub async fn post_login(
+    State(users): State<UserStore>,
+    State(config): State<Config>,
+    jar: SignedCookieJar,
+    Form(data): Form<LoginFormPayload>,
+) -> AppResult {
+    let user = match users.get_by_email(&data.email).await? {
+        Some((user, hash)) if compare_pwd(hash.clone(), data.password.clone()).await? => user,
+        found => {
+            if found.is_none() {
+                compare_pwd(DUMMY_HASH.to_string(), data.password.clone()).await?;
+            }
+            tracing::info!("Invalid login attempt");
+            return Ok(LoginFormPage::new()
+                .set_prepopulated_email(data.email)
+                .maybe_redirect_to(data.redirect_to)
+                .show_invalid_credentials()
+                .show_signup_link(config.allow_signup)
+                .into_response());
+        }
+    };
+
+    let session = users.create_session(user.id).await?;
+    tracing::info!(user_id = user.id, email = %user.email, "{} logged in", user.name);
+
+    let jar = jar.add(session_cookie(&session, &config));
+    let redirect_to = safe_redirect_target(data.redirect_to.as_deref());
+    Ok((jar, Redirect::to(redirect_to)).into_response())
+}
+
+pub async fn post_logout(State(users): State<UserStore>, jar: SignedCookieJar) -> AppResult {
+    if let Some(cookie) = jar.get(SESSION_COOKIE) {
+        users.delete_session(cookie.value()).await?;
+    }
+    let jar = jar.remove(Cookie::build(SESSION_COOKIE).path("/"));
+    Ok((jar, Redirect::to("/login")).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SignupPageQueryParams {
+    invite: Option<String>,
+}
+
+#[tracing::instrument(skip(users, config))]
+pub async fn get_signup(
+    State(users): State<UserStore>,
+    State(config): State<Config>,
+    Query(SignupPageQueryParams { invite }): Query<SignupPageQueryParams>,
+) -> AppResult {
+    let invite = check_signup_allowed(&users, &config, invite.as_deref()).await?;
+    Ok(SignupFormPage::new()
+        .maybe_prepopulated_email(invite.as_ref().and_then(|i| i.email.clone()))
+        .maybe_invite(invite.map(|i| i.token))
+        .into_response())
+}
+
+#[tracing::instrument(skip_all, fields(email = %data.email))]
+pub async fn post_signup(
+    State(users): State<UserStore>,
+    State(config): State<Config>,
+    jar: SignedCookieJar,
+    Form(data): Form<SignupFormPayload>,
+) -> AppResult {
+    let invite = check_signup_allowed(&users, &config, data.invite.as_deref()).await?;
+
+    let form = SignupFormPage::new()
+        .maybe_prepopulated_email(Some(data.email.clone()))
+        .set_prepopulated_name(&data.name)
+        .maybe_invite(data.invite.clone());
+
+    if let Err(msg) = validate_signup(&data, invite.as_ref()) {
+        return Ok(form.set_error(msg).into_response());
+    }
+
+    let hash = hash_pwd(data.password).await?;
+    let result = match &invite {
+        Some(invite) => users
+            .create_user_with_invite(&invite.token, &data.email, data.name.trim(), &hash)
+            .await
+            .map(|user| user.ok_or_else(invite_gone)),
+        None => users
+            .create_user(&data.email, data.name.trim(), &hash, false)
+            .await
+            .map(Ok),
+    };
+    let user: User = match result {
+        Ok(user) => user?,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Ok(form
+                .set_error("An account with this email already exists")
+                .into_response());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    tracing::info!(user_id = user.id, "User signed up");
+
+    let session = users.create_session(user.id).await?;
+    let jar = jar.add(session_cookie(&session, &config));
+    Ok((jar, Redirect::to("/")).into_response())
+}
+
+/// Signing up needs either a valid invite or open sign ups
+async fn check_signup_allowed(
+    users: &UserStore,
+    config: &Config,
+    invite: Option<&str>,
+) -> AppResult<Option<Invite>> {
+    match invite {
+        Some(token) => match users.get_valid_invite(token).await? {
+            Some(invite) => Ok(Some(invite)),
+            None => Err(invite_gone()),
+        },
+        None if config.allow_signup => Ok(None),
+        None => Err(AppError::Forbidden(
+            "Sign up is disabled, ask an admin for an invite".to_string(),
+        )),
+    }
+}
+
+fn invite_gone() -> AppError {
+    AppError::Expired("This invite is invalid or has expired".to_string())
+}
+
+fn validate_signup(data: &SignupFormPayload, invite: Option<&Invite>) -> Result<(), &'static str> {
+    if !data.email.contains('@') {
+        return Err("Please enter a valid email");
+    }
+    if data.name.trim().is_empty() {
+        return Err("Please enter a name");
+    }
+    if data.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err("The password is too short");
+    }
+    if let Some(email) = invite.and_then(|i| i.email.as_deref())
+        && !email.eq_ignore_ascii_case(&data.email)
+    {
+        return Err("This invite is for a different email");
+    }
+    Ok(())
+}
+
+pub(crate) fn session_cookie(session: &Session, config: &Config) -> Cookie<'static> {
+    let max_age = (session.expires_at - chrono::Utc::now())
+        .to_std()
+        .unwrap_or_default();
+    Cookie::build((SESSION_COOKIE, session.id.clone()))
+        .path("/")
+        .http_only(true)
+        .secure(config.secure_cookies)
+        .same_site(SameSite::Lax)
+        .max_age(max_age.try_into().unwrap_or_default())
+        .build()
+}
+
+/// Only allow redirects to local paths, so the login form can't be used as an open redirect
+fn safe_redirect_target(redirect_to: Option<&str>) -> &str {
+    match redirect_to {
+        Some(path) if path.starts_with('/') && !path.starts_with("//") && !path.contains('\\') => {
+            path
+        }
+        _ => "/",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let parts = bcrypt::HashParts::from_str(DUMMY_HASH).expect("a valid bcrypt hash");
+        assert_eq!(parts.get_cost(), crate::HASH_COST);
+    }
+
+    #[test]
+    fn redirects_stay_on_this_site() {
+        assert_eq!(safe_redirect_target(Some("/links?page=2")), "/links?page=2");
+        assert_eq!(safe_redirect_target(None), "/");
+        assert_eq!(safe_redirect_target(Some("https://evil.example")), "/");
+        assert_eq!(safe_redirect_target(Some("//evil.example")), "/");
+        assert_eq!(safe_redirect_target(Some("/\\evil.example")), "/");
+    }
+}
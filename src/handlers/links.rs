@@ -0,0 +1,147 @@
+use axum::{
+    Form,
+    extract::{Path, Query, State, rejection::QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+
+use crate::{
+    analytics::StatsStore,
+    config::Config,
+    errors::AppResult,
+    extractors::{CurrentUser, HxRequest},
+    qr::{QrImage, QrOptions},
+    url_store::{LinkChanges, ShortUrlRow, UrlStore, url_not_found},
+    user_store::User,
+    utm::UtmParams,
+    views::{LinkStatsPage, UrlTableRow, UrlTableRowEdit},
+};
+
+/// The plain table row, used to cancel editing
+pub async fn get_link_row(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    let row = get_owned_row(&u, &user, &s).await?;
+    Ok(UrlTableRow::new(&row).into_response())
+}
+
+pub async fn get_edit_link_row(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    let row = get_owned_row(&u, &user, &s).await?;
+    Ok(UrlTableRowEdit::new(&row).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditUrlForm {
+    url: String,
+}
+
+#[tracing::instrument(skip(u, user), fields(user_id = user.id))]
+pub async fn put_link(
+    CurrentUser(user): CurrentUser,
+    HxRequest(is_hx): HxRequest,
+    State(u): State<UrlStore>,
+    Path(s): Path<String>,
+    Form(EditUrlForm { url }): Form<EditUrlForm>,
+) -> AppResult {
+    get_owned_row(&u, &user, &s).await?;
+    let changes = LinkChanges {
+        longurl: Some(url),
+        ..Default::default()
+    };
+    let row = u.update(&s, changes).await?;
+    tracing::info!("Short url now redirects to {}", row.longurl);
+
+    if is_hx {
+        Ok(UrlTableRow::new(&row).into_response())
+    } else {
+        Ok(Redirect::to("/").into_response())
+    }
+}
+
+#[tracing::instrument(skip(u, user), fields(user_id = user.id))]
+pub async fn delete_link(
+    CurrentUser(user): CurrentUser,
+    HxRequest(is_hx): HxRequest,
+    State(u): State<UrlStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    get_owned_row(&u, &user, &s).await?;
+    u.delete(&s).await?;
+    tracing::info!("Short url deleted");
+
+    if is_hx {
+        // htmx replaces the row with the empty body
+        Ok(StatusCode::OK.into_response())
+    } else {
+        Ok(Redirect::to("/").into_response())
+    }
+}
+
+pub async fn get_link_stats(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    State(stats): State<StatsStore>,
+    Path(s): Path<String>,
+) -> AppResult {
+    let row = get_owned_row(&u, &user, &s).await?;
+    let link_stats = stats.link_stats(&s).await?;
+    Ok(LinkStatsPage::new(user, row, link_stats).into_response())
+}
+
+/// A qr code of the full short url, for print material
+pub async fn get_link_qr(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    Path(s): Path<String>,
+    options: Result<Query<QrOptions>, QueryRejection>,
+) -> AppResult {
+    let Query(options) = options?;
+    let row = get_owned_row(&u, &user, &s).await?;
+    let image = QrImage::render(&config.short_url(&row.shorturl), &row.shorturl, &options)?;
+    Ok(image.into_response())
+}
+
+/// The stats of all links of the user matching the utm parameters in the query, added up
+pub async fn get_campaign_stats(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    State(stats): State<StatsStore>,
+    Query(filter): Query<UtmParams>,
+) -> AppResult {
+    let shorturls: Vec<String> = u
+        .get_all(user.id, &filter)
+        .await?
+        .into_iter()
+        .map(|row| row.shorturl)
+        .collect();
+    let link_stats = stats.combined_stats(&shorturls).await?;
+    Ok(LinkStatsPage::for_campaign(user, &filter, shorturls.len(), link_stats).into_response())
+}
+
+/// Loads the row, making sure the user is allowed to change it
+pub(crate) async fn get_owned_row(u: &UrlStore, user: &User, s: &str) -> AppResult<ShortUrlRow> {
+    get_reachable_row(u, user.id, user.is_admin, s).await
+}
+
+/// Like [`get_owned_row`], but whether links of other users can be reached is up to the caller
+pub(crate) async fn get_reachable_row(
+    u: &UrlStore,
+    user_id: i64,
+    any_owner: bool,
+    s: &str,
+) -> AppResult<ShortUrlRow> {
+    let row = u.get_row(s).await?.ok_or_else(url_not_found)?;
+    if row.owner_id != Some(user_id) && !any_owner {
+        // don't reveal that someone else owns this short url
+        return Err(url_not_found());
+    }
+    Ok(row)
+}
@@ -1 +1,6 @@
-
+pub mod admin;
+pub mod api;
+pub mod auth;
+pub mod links;
+pub mod report;
+pub mod tokens;
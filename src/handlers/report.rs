@@ -0,0 +1,55 @@
+use axum::{
+    Form,
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use std::net::SocketAddr;
+
+use crate::{
+    analytics::{client_ip, hash_ip},
+    config::Config,
+    errors::{AppError, AppResult},
+    moderation::ModerationStore,
+    url_store::{UrlStore, url_not_found},
+    views::ReportLinkPage,
+};
+
+/// Anyone can report a link, no login needed
+pub async fn get_report(State(u): State<UrlStore>, Path(s): Path<String>) -> AppResult {
+    u.get_row(&s).await?.ok_or_else(url_not_found)?;
+    Ok(ReportLinkPage::new(s).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportForm {
+    reason: String,
+}
+
+#[tracing::instrument(skip_all, fields(shorturl = %s))]
+pub async fn post_report(
+    State(u): State<UrlStore>,
+    State(moderation): State<ModerationStore>,
+    State(config): State<Config>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(s): Path<String>,
+    Form(ReportForm { reason }): Form<ReportForm>,
+) -> AppResult {
+    u.get_row(&s).await?.ok_or_else(url_not_found)?;
+
+    // the hash only serves to ignore repeated reports from the same visitor
+    let ip = client_ip(&headers, peer.ip(), config.trust_proxy_headers);
+    let ip_hash = hash_ip(ip, &config.ip_hash_salt);
+    match moderation.report(&s, &reason, Some(&ip_hash)).await {
+        Ok(()) => {
+            tracing::info!("Link reported");
+            Ok(ReportLinkPage::new(s).set_submitted().into_response())
+        }
+        Err(AppError::ValidationError(msg)) => {
+            Ok(ReportLinkPage::new(s).set_error(msg).into_response())
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,69 @@
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+
+use crate::{
+    errors::{AppError, AppResult},
+    extractors::CurrentUser,
+    user_store::{TokenScope, UserStore},
+    views::TokensPage,
+};
+
+/// Longest name a token can be given
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+pub async fn get_tokens(
+    CurrentUser(user): CurrentUser,
+    State(users): State<UserStore>,
+) -> AppResult {
+    let tokens = users.get_api_tokens(user.id).await?;
+    Ok(TokensPage::new(user, tokens).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateTokenForm {
+    name: String,
+    scope: TokenScope,
+}
+
+pub async fn post_token(
+    CurrentUser(user): CurrentUser,
+    State(users): State<UserStore>,
+    Form(CreateTokenForm { name, scope }): Form<CreateTokenForm>,
+) -> AppResult {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "The token name must be between 1 and {MAX_TOKEN_NAME_LENGTH} characters long"
+        )));
+    }
+
+    let (api_token, token) = users.create_api_token(user.id, name, scope).await?;
+    tracing::info!(
+        user_id = user.id,
+        token_id = api_token.id,
+        scope = scope.as_str(),
+        "Api token created"
+    );
+
+    // render instead of redirecting, the plain token can't be looked up again
+    let tokens = users.get_api_tokens(user.id).await?;
+    Ok(TokensPage::new(user, tokens)
+        .set_created(token)
+        .into_response())
+}
+
+pub async fn post_revoke_token(
+    CurrentUser(user): CurrentUser,
+    State(users): State<UserStore>,
+    Path(id): Path<i64>,
+) -> AppResult {
+    if !users.delete_api_token(user.id, id).await? {
+        return Err(AppError::NotFound("Token not found".to_string()));
+    }
+    tracing::info!(user_id = user.id, token_id = id, "Api token revoked");
+    Ok(Redirect::to("/tokens").into_response())
+}
@@ -0,0 +1,188 @@
+use chrono::{TimeDelta, Utc};
+use futures_util::StreamExt;
+use sqlx::{Pool, Sqlite};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::{shared_cache::RedisConnection, url_store::CacheEvictor};
+
+/// Redis channel changed short urls are announced on
+const CHANNEL: &str = "yaus:invalidations";
+
+/// How long changes stay in the change log, far longer than any instance takes to poll it
+const CHANGE_LOG_RETENTION: TimeDelta = TimeDelta::hours(1);
+
+/// How often the change log is pruned
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How long to wait before subscribing again after the redis connection was lost
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Tells the other instances which short urls changed, so they drop them from their caches
+#[derive(Clone, Debug)]
+pub struct Invalidator {
+    /// Identifies this instance, its own changes are already dropped when they are made
+    origin: String,
+    channel: Channel,
+}
+
+#[derive(Clone, Debug)]
+enum Channel {
+    /// A table in the shared database every instance polls
+    ChangeLog {
+        pool: Pool<Sqlite>,
+        poll_interval: Duration,
+    },
+    /// A redis channel every instance subscribes to
+    PubSub(RedisConnection),
+}
+
+impl Invalidator {
+    pub fn change_log(pool: Pool<Sqlite>, poll_interval: Duration) -> Self {
+        Self::new(Channel::ChangeLog {
+            pool,
+            poll_interval,
+        })
+    }
+
+    pub fn pubsub(connection: RedisConnection) -> Self {
+        Self::new(Channel::PubSub(connection))
+    }
+
+    fn new(channel: Channel) -> Self {
+        Self {
+            origin: nanoid::nanoid!(),
+            channel,
+        }
+    }
+
+    /// Announces that `shorturl` changed. Failures are only logged, the other instances still
+    /// drop it once its ttl runs out.
+    pub async fn publish(&self, shorturl: &str) {
+        let result = match &self.channel {
+            Channel::ChangeLog { pool, .. } => {
+                let now = Utc::now();
+                sqlx::query!(
+                    "INSERT INTO cache_invalidations (shorturl, origin, created_at) VALUES (?, ?, ?)",
+                    shorturl,
+                    self.origin,
+                    now
+                )
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+            }
+            Channel::PubSub(connection) => connection
+                .publish(CHANNEL, &format!("{}:{shorturl}", self.origin))
+                .await
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Unable to tell other instances that {shorturl} changed: {e}");
+        }
+    }
+
+    /// Spawn a background task that drops the short urls other instances changed from the
+    /// caches `evictor` holds
+    pub fn spawn_listener(&self, evictor: CacheEvictor) -> JoinHandle<()> {
+        let invalidator = self.clone();
+        tokio::spawn(async move {
+            match &invalidator.channel {
+                Channel::ChangeLog {
+                    pool,
+                    poll_interval,
+                } => {
+                    invalidator
+                        .poll_change_log(pool, *poll_interval, &evictor)
+                        .await
+                }
+                Channel::PubSub(connection) => invalidator.listen(connection, &evictor).await,
+            }
+        })
+    }
+
+    async fn poll_change_log(
+        &self,
+        pool: &Pool<Sqlite>,
+        interval: Duration,
+        evictor: &CacheEvictor,
+    ) {
+        // earlier changes don't matter, the caches start out empty
+        let mut last_id = sqlx::query_scalar!(
+            r#"SELECT COALESCE(MAX(id), 0) as "id!: i64" FROM cache_invalidations"#
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("Unable to read the cache invalidation log: {}", e);
+            0
+        });
+        let mut last_pruned = Instant::now();
+
+        loop {
+            tokio::time::sleep(interval).await;
+            let changes = sqlx::query!(
+                r#"SELECT id as "id!", shorturl, origin FROM cache_invalidations WHERE id > ? ORDER BY id"#,
+                last_id
+            )
+            .fetch_all(pool)
+            .await;
+            match changes {
+                Ok(changes) => {
+                    for change in changes {
+                        last_id = change.id;
+                        if change.origin != self.origin {
+                            evictor.evict(&change.shorturl).await;
+                        }
+                    }
+                }
+                Err(e) => tracing::error!("Unable to read the cache invalidation log: {}", e),
+            }
+
+            if last_pruned.elapsed() >= PRUNE_INTERVAL {
+                last_pruned = Instant::now();
+                let cutoff = Utc::now() - CHANGE_LOG_RETENTION;
+                let pruned = sqlx::query!(
+                    "DELETE FROM cache_invalidations WHERE created_at < ?",
+                    cutoff
+                )
+                .execute(pool)
+                .await;
+                match pruned {
+                    Ok(result) => tracing::debug!(
+                        "Removed {} old cache invalidations",
+                        result.rows_affected()
+                    ),
+                    Err(e) => tracing::error!("Unable to prune the cache invalidation log: {}", e),
+                }
+            }
+        }
+    }
+
+    /// Changes announced while the subscription is down are missed, local caches in front of
+    /// redis only keep entries for a few seconds so they catch up on their own
+    async fn listen(&self, connection: &RedisConnection, evictor: &CacheEvictor) {
+        loop {
+            match connection.subscribe(CHANNEL).await {
+                Ok(mut messages) => {
+                    while let Some(message) = messages.next().await {
+                        let Ok(payload) = message.get_payload::<String>() else {
+                            continue;
+                        };
+                        if let Some((origin, shorturl)) = payload.split_once(':')
+                            && origin != self.origin
+                        {
+                            evictor.evict(shorturl).await;
+                        }
+                    }
+                    tracing::warn!("Lost the redis subscription for cache invalidations");
+                }
+                Err(e) => {
+                    tracing::warn!("Unable to subscribe to cache invalidations in redis: {e}")
+                }
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+}
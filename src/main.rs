@@ -1,29 +1,90 @@
 use crate::{
-    cache::TtlCache,
-    errors::{AppError, AppResult},
-    url_store::UrlStore,
-    views::{DashboardPageBuilder, LoginFormPage, LoginFormPayload, UrlTableRow},
+    analytics::{CLICK_CHANNEL_CAPACITY, ClickEvent, StatsStore, client_ip, spawn_click_recorder},
+    cli::Cli,
+    config::Config,
+    destination::DestinationPolicy,
+    errors::{AppError, AppResult, negotiate_error_format},
+    extractors::{CurrentUser, HxRequest},
+    handlers::{
+        admin::{
+            get_admin_links, get_blocklist, get_invites, get_metrics, get_reports, post_blocklist,
+            post_delete_blocklist_entry, post_disable_link, post_dismiss_reports, post_enable_link,
+            post_invite,
+        },
+        api,
+        auth::{get_login, get_signup, post_login, post_logout, post_signup},
+        links::{
+            delete_link, get_campaign_stats, get_edit_link_row, get_link_qr, get_link_row,
+            get_link_stats, put_link,
+        },
+        report::{get_report, post_report},
+        tokens::{get_tokens, post_revoke_token, post_token},
+    },
+    invalidation::Invalidator,
+    moderation::{Blocklist, ModerationStore},
+    redirect::RedirectType,
+    serde_utils::empty_string_as_none,
+    shared_cache::{CacheSetupError, build_cache, connect_redis},
+    url_store::{Destination, NewShortUrl, UrlStore, url_not_found},
+    user_store::UserStore,
+    utm::UtmParams,
+    views::{AddUrlError, DashboardPageBuilder, LinkDisabledPage, LinkExpiredPage, UrlTableRow},
 };
 use axum::{
     Form, debug_handler,
-    extract::{FromRequestParts, Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Redirect, Response},
-    routing::get,
+    extract::{ConnectInfo, FromRef, Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
 };
+use axum_extra::extract::cookie::Key;
 use bcrypt::BcryptError;
-use serde::Deserialize;
-use std::{convert::Infallible, env, panic, time::Duration};
-use tokio::signal::ctrl_c;
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Parser;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{signal::ctrl_c, sync::mpsc};
 use tower_http::services::ServeDir;
 
+mod analytics;
 mod cache;
+mod cli;
+mod config;
+mod destination;
 mod errors;
+mod extractors;
 mod handlers;
+mod invalidation;
+mod moderation;
+mod qr;
+mod redirect;
 //mod partials;
+mod serde_utils;
+mod shared_cache;
+mod short_code;
+#[cfg(test)]
+mod test_utils;
 mod url_store;
+mod user_store;
+mod utm;
 mod views;
 
+/// Shared state handed to every handler, individual parts can be extracted with `State<T>`
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    url_store: UrlStore,
+    user_store: UserStore,
+    stats_store: StatsStore,
+    moderation: ModerationStore,
+    blocklist: Blocklist,
+    config: Config,
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.session_key.clone()
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Initialize the tracing subscriber for logging
@@ -33,133 +94,312 @@ async fn main() {
     //load the environment variables from the .env file
     dotenvy::dotenv().ok();
 
+    let cli = Cli::parse();
+    let config = Config::from_env();
+
     // Initialize the SQLite connection pool
-    let sqlite_pool =
-        sqlx::SqlitePool::connect(&env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
-            .await
-            .expect("Failed to create SQLite pool");
+    let sqlite_pool = sqlx::SqlitePool::connect(&config.database_url)
+        .await
+        .expect("Failed to create SQLite pool");
+
+    // Run one-off commands instead of the server
+    if let Some(command) = cli.command {
+        let user_store = UserStore::new(sqlite_pool.clone(), config.session_ttl);
+        let result = command.run(&user_store).await;
+        sqlite_pool.close().await;
+        if let Err(e) = result {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // Initialize the cache with a TTL of 60 seconds and a cleanup interval of 10 seconds
-    let (cache, cleaner_handle) =
-        TtlCache::new(Duration::from_secs(60), Duration::from_secs(10)).await;
+    let caches = async {
+        let redis = connect_redis(config.cache_backend, config.redis_url.as_deref()).await?;
+        // Links are cached for 60 seconds after their last visit
+        let links = build_cache(&config, redis.as_ref(), "link", Duration::from_secs(60)).await?;
+        // Unknown short urls are remembered separately, so probing them can't push out real links
+        let missing =
+            build_cache(&config, redis.as_ref(), "missing", config.missing_cache_ttl).await?;
+        Ok::<_, CacheSetupError>((redis, links, missing))
+    };
+    let (redis, (cache, cleaner_handle), (missing_cache, missing_cleaner_handle)) =
+        match caches.await {
+            Ok(caches) => caches,
+            Err(e) => {
+                sqlite_pool.close().await;
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        };
 
-    let url_store = url_store::UrlStore::new(sqlite_pool.clone(), cache.clone()).await;
+    // Clicks are written in batches by a background task
+    let (stats_tx, stats_rx) = mpsc::channel(CLICK_CHANNEL_CAPACITY);
+    let click_recorder_handle = spawn_click_recorder(sqlite_pool.clone(), stats_rx);
 
-    let router = axum::Router::new()
-        .route("/", axum::routing::get(get_hompeage))
-        .route("/add", axum::routing::post(post_add_url))
-        .route("/login", get(get_login).post(post_login))
-        .route("/{s}", axum::routing::get(get_redirect_to_url))
-        .nest_service("/static", ServeDir::new("./static"))
-        .with_state(url_store);
+    let blocklist = Blocklist::load(sqlite_pool.clone(), config.blocklist_file.as_deref())
+        .await
+        .unwrap_or_else(|e| panic!("Failed to load the blocklist: {e}"));
+
+    // Changes are announced over redis when it's there, otherwise through the database
+    let invalidator = match &redis {
+        Some(connection) => Invalidator::pubsub(connection.clone()),
+        None => Invalidator::change_log(sqlite_pool.clone(), config.invalidation_poll_interval),
+    };
+
+    let url_store = url_store::UrlStore::new(
+        sqlite_pool.clone(),
+        cache,
+        missing_cache,
+        config.short_code_strategy.build(),
+        stats_tx,
+        DestinationPolicy::new(&config.extra_url_schemes, &config.public_url),
+        blocklist.clone(),
+        config.default_redirect,
+        invalidator,
+    )
+    .await;
+    let invalidation_handle = url_store.spawn_invalidation_listener();
+    let user_store = UserStore::new(sqlite_pool.clone(), config.session_ttl);
+
+    // Periodically drop expired sessions from the database
+    let session_cleaner_handle = user_store.spawn_session_cleaner(Duration::from_secs(60 * 60));
+
+    let state = AppState {
+        url_store,
+        user_store,
+        stats_store: StatsStore::new(sqlite_pool.clone()),
+        moderation: ModerationStore::new(sqlite_pool.clone()),
+        blocklist,
+        config,
+    };
+
+    let router = app(state);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .expect("Failed to bind to address");
-    axum::serve(listener, router)
-        .with_graceful_shutdown(shutdown_signal())
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .expect("Failed to start server");
+
+    // The router and with it every click sender is gone now, wait for the recorder to write what's left
+    if tokio::time::timeout(Duration::from_secs(10), click_recorder_handle)
         .await
-        .expect("Failed to start server");
+        .is_err()
+    {
+        tracing::error!("Click recorder did not finish in time, some clicks were lost");
+    }
 
     //close the SQLite pool gracefully
     sqlite_pool.close().await;
-    cleaner_handle.abort();
+    for handle in [cleaner_handle, missing_cleaner_handle]
+        .into_iter()
+        .flatten()
+    {
+        handle.abort();
+    }
+    invalidation_handle.abort();
+    session_cleaner_handle.abort();
     println!("Server has been shut down gracefully.");
 }
 
+/// Every route of the shortener
+fn app(state: AppState) -> axum::Router {
+    let api_v1 = axum::Router::new()
+        .route("/links", get(api::list_links).post(api::create_link))
+        .route(
+            "/links/{s}",
+            get(api::get_link)
+                .patch(api::update_link)
+                .delete(api::delete_link),
+        );
+
+    axum::Router::new()
+        .route("/", axum::routing::get(get_hompeage))
+        .route("/add", axum::routing::post(post_add_url))
+        .route(
+            "/links/{s}",
+            get(get_link_row).put(put_link).delete(delete_link),
+        )
+        .route("/links/{s}/edit", get(get_edit_link_row))
+        .route("/links/{s}/stats", get(get_link_stats))
+        .route("/links/{s}/qr", get(get_link_qr))
+        .route("/stats", get(get_campaign_stats))
+        .route("/admin/links", get(get_admin_links))
+        .route("/admin/invites", get(get_invites).post(post_invite))
+        .route("/admin/reports", get(get_reports))
+        .route("/admin/reports/{s}/dismiss", post(post_dismiss_reports))
+        .route("/admin/links/{s}/disable", post(post_disable_link))
+        .route("/admin/links/{s}/enable", post(post_enable_link))
+        .route("/admin/blocklist", get(get_blocklist).post(post_blocklist))
+        .route(
+            "/admin/blocklist/{id}/delete",
+            post(post_delete_blocklist_entry),
+        )
+        .route("/metrics", get(get_metrics))
+        .route("/tokens", get(get_tokens).post(post_token))
+        .route("/tokens/{id}/revoke", post(post_revoke_token))
+        .route("/login", get(get_login).post(post_login))
+        .route("/logout", post(post_logout))
+        .route("/signup", get(get_signup).post(post_signup))
+        .nest("/api/v1", api_v1)
+        .route("/{s}", axum::routing::get(get_redirect_to_url))
+        .route("/{s}/{*rest}", axum::routing::get(get_redirect_to_url))
+        .route("/{s}/report", get(get_report).post(post_report))
+        .nest_service("/static", ServeDir::new("./static"))
+        .layer(axum::middleware::from_fn(negotiate_error_format))
+        .with_state(state)
+}
+
 async fn shutdown_signal() {
     ctrl_c()
         .await
         .expect("Failed to listen for shutdown signal");
 }
 
-async fn get_hompeage(State(u): State<UrlStore>) -> Response {
-    let values = u.get_all().await.unwrap();
-    let homepage = DashboardPageBuilder::new().set_rows(values);
+async fn get_hompeage(
+    CurrentUser(user): CurrentUser,
+    State(u): State<UrlStore>,
+    Query(filter): Query<UtmParams>,
+) -> AppResult {
+    let values = u.get_all(user.id, &filter).await?;
+    let homepage = DashboardPageBuilder::new()
+        .set_user(user)
+        .set_rows(values)
+        .set_filter(filter);
 
-    (StatusCode::OK, homepage).into_response()
+    Ok((StatusCode::OK, homepage).into_response())
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct AddUrlForm {
     url: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    alias: Option<String>,
+    /// Seconds from now until the link expires
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    expires_in: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    max_clicks: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    redirect_type: Option<RedirectType>,
+    /// Unchecked checkboxes are not sent at all
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(flatten)]
+    utm: UtmParams,
 }
 
 async fn post_add_url(
+    CurrentUser(user): CurrentUser,
     HxRequest(is_hx): HxRequest,
     State(u): State<UrlStore>,
-    Form(AddUrlForm { url }): Form<AddUrlForm>,
-) -> Response {
-    match u.insert(url).await {
-        Err(e) => {
-            tracing::error!("Error inserting URL: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("error: {e}")).into_response()
+    Form(form): Form<AddUrlForm>,
+) -> AppResult {
+    let result = match form.expires_in.map(expiry_after).transpose() {
+        Ok(expires_at) => {
+            let new = NewShortUrl {
+                longurl: form.url,
+                alias: form.alias,
+                expires_at,
+                max_clicks: form.max_clicks,
+                redirect_type: form.redirect_type,
+                passthrough: form.passthrough,
+                utm: form.utm,
+            };
+            u.insert(user.id, new).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if is_hx => {
+            tracing::info!("Error inserting URL: {}", e);
+            Ok(AddUrlError::new(e.status(), e.public_message()).into_response())
         }
+        Err(e) => Err(e),
         Ok(val) if is_hx => {
             tracing::debug!("this is an htmx request.");
-            UrlTableRow::new(&val).into_response()
+            Ok(UrlTableRow::new(&val).into_response())
         }
-        Ok(_val) => Redirect::to("/").into_response(),
+        Ok(_val) => Ok(Redirect::to("/").into_response()),
     }
 }
 
-pub struct HxRequest(bool);
-
-impl<S> FromRequestParts<S> for HxRequest
-where
-    S: Send + Sync,
-{
-    type Rejection = Infallible;
-    async fn from_request_parts(
-        parts: &mut axum::http::request::Parts,
-        state: &S,
-    ) -> Result<Self, Self::Rejection> {
-        // If the "Hx-Request" header is present, the value is always "true" as per https://htmx.org/reference/#request_headers
-        Ok(Self(parts.headers.contains_key("Hx-Request")))
+/// When a link created now expires, if it expires after `secs` seconds
+fn expiry_after(secs: i64) -> AppResult<DateTime<Utc>> {
+    if secs <= 0 {
+        return Err(AppError::ValidationError(
+            "The expiry must be in the future".to_string(),
+        ));
     }
+    TimeDelta::try_seconds(secs)
+        .and_then(|delta| Utc::now().checked_add_signed(delta))
+        .ok_or_else(|| AppError::ValidationError("The expiry is too far in the future".to_string()))
 }
-#[debug_handler]
-#[tracing::instrument]
-async fn get_redirect_to_url(Path(s): Path<String>, State(u): State<UrlStore>) -> AppResult {
-    match u.get(s).await? {
-        Some(url) => {
-            tracing::info!("Redirecting to URL: {}", url);
-            Ok(axum::response::Redirect::to(&url).into_response())
+
+/// The short url of a visit, anything after it is read from the raw uri so its encoding is kept
+#[derive(Debug, serde::Deserialize)]
+struct VisitPath {
+    s: String,
+}
+
+#[debug_handler(state = AppState)]
+#[tracing::instrument(skip(u, config, uri, headers))]
+async fn get_redirect_to_url(
+    Path(VisitPath { s }): Path<VisitPath>,
+    State(u): State<UrlStore>,
+    State(config): State<Config>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> AppResult {
+    let extra_path = uri
+        .path()
+        .trim_start_matches('/')
+        .split_once('/')
+        .map(|(_, rest)| rest)
+        .filter(|rest| !rest.is_empty());
+    match u.get(s.clone()).await? {
+        Some(Destination::Url(target)) => {
+            let target = if target.passthrough {
+                target.forward(extra_path, uri.query())
+            } else if extra_path.is_some() {
+                return Err(url_not_found());
+            } else {
+                target
+            };
+            if target.click_limited && !u.count_click(&s).await? {
+                tracing::info!("URL has used up its clicks");
+                return Ok(LinkExpiredPage::new().into_response());
+            }
+            tracing::info!("Redirecting to URL: {}", target.url);
+            let ip = client_ip(&headers, peer.ip(), config.trust_proxy_headers);
+            u.record_click(ClickEvent::new(s, &headers, Some(ip), &config.ip_hash_salt));
+            Ok(target.into_response())
+        }
+        Some(Destination::Expired) => {
+            tracing::info!("URL has expired");
+            Ok(LinkExpiredPage::new().into_response())
+        }
+        Some(Destination::Disabled) => {
+            tracing::info!("URL is disabled");
+            Ok(LinkDisabledPage::new().into_response())
         }
         None => {
             tracing::warn!("URL not found");
-            Err(AppError::custom(StatusCode::NOT_FOUND, "Url not found"))
+            Err(url_not_found())
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
-struct LoginPageQueryParams {
-    redirect_to: Option<String>,
-}
-
-#[tracing::instrument]
-async fn get_login(
-    Query(LoginPageQueryParams { redirect_to }): Query<LoginPageQueryParams>,
-) -> AppResult {
-    Ok(LoginFormPage::new()
-        .maybe_redirect_to(redirect_to)
-        .into_response())
-}
-
-#[tracing::instrument]
-async fn post_login(Form(data): Form<LoginFormPayload>) -> AppResult {
-    //TODO implement logic
-    Ok(LoginFormPage::new()
-        .set_prepopulated_email(data.email)
-        .maybe_redirect_to(data.redirect_to)
-        .show_invalid_credentials()
-        .into_response())
-}
-
 static HASH_COST: u32 = 10;
 
-async fn compare_pwd<'a>(hash: String, pwd: String) -> Result<bool, BcryptError> {
+async fn compare_pwd(hash: String, pwd: String) -> Result<bool, BcryptError> {
     tokio::task::spawn_blocking(move || bcrypt::verify(pwd, &hash))
         .await
         .expect("bcrypt either panicked or task was cancelled")
@@ -169,3 +409,112 @@ async fn hash_pwd(plain_pwd: String) -> Result<String, BcryptError> {
         .await
         .expect("bcrypt either panicked or task was cancelled")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+    use axum::{body::Body, http::Request};
+
+    /// The status a visit of `path` gets
+    async fn visit(state: &AppState, path: &str) -> StatusCode {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        test_utils::send(state, request).await.status()
+    }
+
+    async fn click_count(pool: &sqlx::SqlitePool, shorturl: &str) -> i64 {
+        sqlx::query_scalar!(
+            "SELECT click_count FROM shorturls WHERE shorturl = ?",
+            shorturl
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    /// A state with a link `abc` that can be visited `max_clicks` times, and its database
+    async fn click_limited_link(max_clicks: i64) -> (AppState, sqlx::SqlitePool) {
+        let pool = test_utils::pool().await;
+        let state = test_utils::state(&pool).await;
+        let owner = state
+            .user_store
+            .create_user("owner@example.com", "Owner", "hash", false)
+            .await
+            .unwrap();
+        let link = NewShortUrl {
+            longurl: "https://example.com/a".to_string(),
+            alias: Some("abc".to_string()),
+            max_clicks: Some(max_clicks),
+            ..Default::default()
+        };
+        state.url_store.insert(owner.id, link).await.unwrap();
+        (state, pool)
+    }
+
+    #[tokio::test]
+    async fn extra_paths_that_are_not_forwarded_use_up_no_clicks() {
+        let (state, pool) = click_limited_link(1).await;
+        assert_eq!(visit(&state, "/abc/extra").await, StatusCode::NOT_FOUND);
+        assert_eq!(click_count(&pool, "abc").await, 0);
+
+        assert_eq!(visit(&state, "/abc").await, StatusCode::FOUND);
+        assert_eq!(click_count(&pool, "abc").await, 1);
+    }
+
+    #[tokio::test]
+    async fn click_limited_links_expire_once_their_clicks_are_used_up() {
+        let (state, pool) = click_limited_link(2).await;
+        assert_eq!(visit(&state, "/abc").await, StatusCode::FOUND);
+        assert_eq!(visit(&state, "/abc").await, StatusCode::FOUND);
+        assert_eq!(visit(&state, "/abc").await, StatusCode::GONE);
+        assert_eq!(click_count(&pool, "abc").await, 2);
+    }
+
+    #[tokio::test]
+    async fn the_dashboard_only_lists_links_of_the_user() {
+        let pool = test_utils::pool().await;
+        let state = test_utils::state(&pool).await;
+        let alice = test_utils::user(&pool, "alice@example.com", false).await;
+        let bob = test_utils::user(&pool, "bob@example.com", false).await;
+        test_utils::links(&pool, alice.id, &["alicelink"]).await;
+        test_utils::links(&pool, bob.id, &["boblink"]).await;
+
+        let request = Request::get("/")
+            .header("Cookie", test_utils::session_cookie(&state, alice.id).await)
+            .body(Body::empty())
+            .unwrap();
+        let response = test_utils::send(&state, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = test_utils::text_body(response).await;
+        assert!(page.contains("alicelink"));
+        assert!(!page.contains("boblink"));
+    }
+
+    #[test]
+    fn links_expire_the_given_seconds_from_now() {
+        let before = Utc::now();
+        let expires_at = expiry_after(3600).expect("a valid expiry");
+        assert!(expires_at >= before + TimeDelta::hours(1));
+        assert!(expires_at <= Utc::now() + TimeDelta::hours(1));
+    }
+
+    #[test]
+    fn expiries_that_are_not_in_the_future_are_rejected() {
+        for secs in [0, -1, i64::MIN] {
+            assert!(matches!(
+                expiry_after(secs),
+                Err(AppError::ValidationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn expiries_past_the_representable_range_are_rejected() {
+        for secs in [i64::MAX, i64::MAX / 1000, 400_000 * 365 * 24 * 3600] {
+            assert!(matches!(
+                expiry_after(secs),
+                Err(AppError::ValidationError(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite};
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use url::Url;
+
+use crate::errors::{AppError, AppResult};
+
+/// Domains and host patterns that links can't point to.
+///
+/// Patterns come from the `BLOCKLIST_FILE` and the `blocklist` table admins edit. A plain
+/// domain blocks itself and all its subdomains, `*` matches any run of characters.
+#[derive(Clone, Debug)]
+pub struct Blocklist {
+    sqlite_pool: Pool<Sqlite>,
+    file_patterns: Arc<Vec<String>>,
+    /// Copy of the table, so redirects don't need a query
+    db_patterns: Arc<RwLock<Vec<String>>>,
+}
+
+impl Blocklist {
+    /// Loads the patterns of the table and, if given, the file.
+    ///
+    /// The file has one pattern per line, empty lines and lines starting with `#` are ignored.
+    pub async fn load(sqlite_pool: Pool<Sqlite>, file: Option<&Path>) -> Result<Self, String> {
+        let mut file_patterns = Vec::new();
+        if let Some(file) = file {
+            let content = tokio::fs::read_to_string(file)
+                .await
+                .map_err(|e| format!("unable to read {}: {e}", file.display()))?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                file_patterns
+                    .push(normalize_pattern(line).map_err(|e| format!("{}: {e}", file.display()))?);
+            }
+            tracing::info!(
+                "Loaded {} blocklist patterns from {}",
+                file_patterns.len(),
+                file.display()
+            );
+        }
+
+        let blocklist = Self {
+            sqlite_pool,
+            file_patterns: Arc::new(file_patterns),
+            db_patterns: Arc::default(),
+        };
+        blocklist.reload().await.map_err(|e| e.to_string())?;
+        Ok(blocklist)
+    }
+
+    /// Refreshes the in memory copy of the table
+    async fn reload(&self) -> AppResult<()> {
+        let patterns = sqlx::query_scalar!("SELECT pattern FROM blocklist")
+            .fetch_all(&self.sqlite_pool)
+            .await?;
+        *self
+            .db_patterns
+            .write()
+            .expect("blocklist lock is poisoned") = patterns;
+        Ok(())
+    }
+
+    /// The pattern blocking the host of `url`, if any
+    pub fn blocked_by(&self, url: &str) -> Option<String> {
+        let host = Url::parse(url).ok()?.host_str()?.to_ascii_lowercase();
+        let db_patterns = self.db_patterns.read().expect("blocklist lock is poisoned");
+        self.file_patterns
+            .iter()
+            .chain(db_patterns.iter())
+            .find(|pattern| pattern_matches(pattern, &host))
+            .cloned()
+    }
+
+    pub fn file_patterns(&self) -> &[String] {
+        &self.file_patterns
+    }
+
+    /// The patterns admins added, newest first
+    pub async fn entries(&self) -> AppResult<Vec<BlocklistEntry>> {
+        let entries = sqlx::query_as!(
+            BlocklistEntry,
+            r#"SELECT id, pattern, created_at as "created_at: DateTime<Utc>"
+            FROM blocklist ORDER BY created_at DESC"#
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+        Ok(entries)
+    }
+
+    pub async fn add(&self, pattern: &str, created_by: i64) -> AppResult<()> {
+        let pattern = normalize_pattern(pattern).map_err(AppError::ValidationError)?;
+        let created_at = Utc::now();
+        let result = sqlx::query!(
+            "INSERT INTO blocklist (pattern, created_by, created_at) VALUES (?, ?, ?)",
+            pattern,
+            created_by,
+            created_at
+        )
+        .execute(&self.sqlite_pool)
+        .await;
+        match result {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(AppError::Conflict(format!(
+                    "\"{pattern}\" is already blocked"
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.reload().await
+    }
+
+    pub async fn remove(&self, id: i64) -> AppResult<()> {
+        let result = sqlx::query!("DELETE FROM blocklist WHERE id = ?", id)
+            .execute(&self.sqlite_pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound("Blocklist entry not found".to_string()));
+        }
+        self.reload().await
+    }
+}
+
+/// Lowercases a pattern and converts international domains to punycode, so they
+/// compare equal to the hosts of normalized destinations
+fn normalize_pattern(pattern: &str) -> Result<String, String> {
+    let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+    if pattern.is_empty() {
+        return Err("the pattern is empty".to_string());
+    }
+    if pattern.contains('*') {
+        if !pattern
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '*'))
+        {
+            return Err(format!(
+                "\"{pattern}\" can only contain letters, numbers, '.', '-' and '*'"
+            ));
+        }
+        return Ok(pattern);
+    }
+    Url::parse(&format!("http://{pattern}"))
+        .ok()
+        .and_then(|url| {
+            // anything besides a bare host (a path, a port, ...) is not a domain
+            (url.path() == "/" && url.port().is_none() && url.query().is_none())
+                .then(|| url.host_str().map(str::to_string))
+                .flatten()
+        })
+        .ok_or_else(|| format!("\"{pattern}\" is not a domain"))
+}
+
+fn pattern_matches(pattern: &str, host: &str) -> bool {
+    if pattern.contains('*') {
+        glob_matches(pattern.as_bytes(), host.as_bytes())
+    } else {
+        host == pattern
+            || host
+                .strip_suffix(pattern)
+                .is_some_and(|rest| rest.ends_with('.'))
+    }
+}
+
+/// `*` matches any run of characters, everything else matches itself
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was seen and which text position it currently covers up to
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, covered)) = backtrack {
+            // let the last `*` swallow one more character
+            p = star + 1;
+            t = covered + 1;
+            backtrack = Some((star, covered + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[derive(Debug, Clone)]
+pub struct BlocklistEntry {
+    pub id: i64,
+    pub pattern: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Longest reason a visitor can give when reporting a link
+pub const MAX_REPORT_REASON_LENGTH: usize = 1000;
+
+/// The queue of reported links
+#[derive(Clone, Debug)]
+pub struct ModerationStore {
+    sqlite_pool: Pool<Sqlite>,
+}
+
+impl ModerationStore {
+    pub fn new(sqlite_pool: Pool<Sqlite>) -> Self {
+        Self { sqlite_pool }
+    }
+
+    /// Files a report, repeated reports of the same visitor are ignored until the link is reviewed
+    pub async fn report(
+        &self,
+        shorturl: &str,
+        reason: &str,
+        ip_hash: Option<&str>,
+    ) -> AppResult<()> {
+        let reason = reason.trim();
+        if reason.is_empty() || reason.chars().count() > MAX_REPORT_REASON_LENGTH {
+            return Err(AppError::ValidationError(format!(
+                "Please describe the problem in at most {MAX_REPORT_REASON_LENGTH} characters"
+            )));
+        }
+        let created_at = Utc::now();
+        sqlx::query!(
+            "INSERT OR IGNORE INTO reports (shorturl, reason, reporter_ip_hash, created_at) VALUES (?, ?, ?, ?)",
+            shorturl,
+            reason,
+            ip_hash,
+            created_at
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Links with unresolved reports, the most reported first
+    pub async fn open_reports(&self) -> AppResult<Vec<ReportedLink>> {
+        let rows = sqlx::query!(
+            r#"SELECT reports.shorturl, shorturls.longurl, shorturls.disabled_at as "disabled_at: DateTime<Utc>",
+            reports.reason, reports.created_at as "created_at: DateTime<Utc>"
+            FROM reports INNER JOIN shorturls ON shorturls.shorturl = reports.shorturl
+            WHERE reports.resolved_at IS NULL ORDER BY reports.created_at"#
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+
+        let mut links: Vec<ReportedLink> = Vec::new();
+        for row in rows {
+            match links.iter_mut().find(|l| l.shorturl == row.shorturl) {
+                Some(link) => link.reasons.push(row.reason),
+                None => links.push(ReportedLink {
+                    shorturl: row.shorturl,
+                    longurl: row.longurl,
+                    disabled: row.disabled_at.is_some(),
+                    first_reported_at: row.created_at,
+                    reasons: vec![row.reason],
+                }),
+            }
+        }
+        links.sort_by_key(|link| std::cmp::Reverse(link.reasons.len()));
+        Ok(links)
+    }
+
+    /// Closes every open report of a link, after it was disabled or found to be fine
+    pub async fn resolve_reports(&self, shorturl: &str) -> AppResult<()> {
+        let now = Utc::now();
+        sqlx::query!(
+            "UPDATE reports SET resolved_at = ? WHERE shorturl = ? AND resolved_at IS NULL",
+            now,
+            shorturl
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A link in the moderation queue with all its open reports
+#[derive(Debug, Clone)]
+pub struct ReportedLink {
+    pub shorturl: String,
+    pub longurl: String,
+    pub disabled: bool,
+    pub first_reported_at: DateTime<Utc>,
+    pub reasons: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_block_themselves_and_their_subdomains() {
+        assert!(pattern_matches("evil.example", "evil.example"));
+        assert!(pattern_matches("evil.example", "www.evil.example"));
+        assert!(pattern_matches("evil.example", "a.b.evil.example"));
+        assert!(!pattern_matches("evil.example", "notevil.example"));
+        assert!(!pattern_matches("evil.example", "evil.example.org"));
+    }
+
+    #[test]
+    fn stars_match_any_run_of_characters() {
+        assert!(pattern_matches("*.evil.example", "www.evil.example"));
+        assert!(!pattern_matches("*.evil.example", "evil.example"));
+        assert!(pattern_matches("phish*.example", "phishing.example"));
+        assert!(pattern_matches("phish*.example", "phish.example"));
+        assert!(pattern_matches("*bad*", "very-bad-host.example"));
+        assert!(!pattern_matches("phish*.example", "phishing.example.org"));
+        assert!(glob_matches(b"a*b*c", b"aXbYbZc"));
+        assert!(!glob_matches(b"a*b*c", b"aXbYbZ"));
+    }
+
+    #[test]
+    fn patterns_are_normalized() {
+        assert_eq!(
+            normalize_pattern("  Evil.Example. ").as_deref(),
+            Ok("evil.example")
+        );
+        assert_eq!(
+            normalize_pattern("bücher.example").as_deref(),
+            Ok("xn--bcher-kva.example")
+        );
+        assert_eq!(
+            normalize_pattern("*.Evil.example").as_deref(),
+            Ok("*.evil.example")
+        );
+    }
+
+    #[test]
+    fn patterns_must_be_domains() {
+        assert!(normalize_pattern("").is_err());
+        assert!(normalize_pattern("evil.example/path").is_err());
+        assert!(normalize_pattern("evil.example:8080").is_err());
+        assert!(normalize_pattern("*.evil.example/path").is_err());
+    }
+}
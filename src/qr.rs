@@ -0,0 +1,305 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use qrcode::{EcLevel, QrCode, render::svg};
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+use crate::errors::{AppError, AppResult};
+
+/// Smallest and largest width in pixels a qr code can be requested with
+const QR_SIZE: std::ops::RangeInclusive<u32> = 64..=2048;
+
+/// Empty modules scanners need around the code, as the qr spec requires
+const QUIET_ZONE_MODULES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Svg => "image/svg+xml",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+/// How much of the code can be damaged or covered and still be read, from 7% (`l`) to 30% (`h`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrErrorCorrection {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+
+impl From<QrErrorCorrection> for EcLevel {
+    fn from(level: QrErrorCorrection) -> Self {
+        match level {
+            QrErrorCorrection::L => EcLevel::L,
+            QrErrorCorrection::M => EcLevel::M,
+            QrErrorCorrection::Q => EcLevel::Q,
+            QrErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+/// A colour written as `rrggbb`, with or without a leading `#`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color([u8; 3]);
+
+impl Color {
+    const BLACK: Color = Color([0, 0, 0]);
+    const WHITE: Color = Color([255, 255, 255]);
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        let invalid = || format!("\"{s}\" is not a colour, use rrggbb hex like 1a2b3c");
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Self([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// How a qr code is rendered, read from the query string
+#[derive(Debug, Clone, Deserialize)]
+pub struct QrOptions {
+    #[serde(default)]
+    pub format: QrFormat,
+    /// Width and height in pixels, the code is scaled by whole modules so it can end up a bit larger
+    #[serde(default = "default_size")]
+    pub size: u32,
+    #[serde(default)]
+    pub ec: QrErrorCorrection,
+    #[serde(default = "default_fg")]
+    pub fg: Color,
+    #[serde(default = "default_bg")]
+    pub bg: Color,
+    /// Ask the browser to save the image instead of showing it
+    #[serde(default)]
+    pub download: bool,
+}
+
+fn default_size() -> u32 {
+    256
+}
+
+fn default_fg() -> Color {
+    Color::BLACK
+}
+
+fn default_bg() -> Color {
+    Color::WHITE
+}
+
+/// A rendered qr code, ready to be sent
+pub struct QrImage {
+    format: QrFormat,
+    /// File name offered when downloading, without extension
+    name: String,
+    download: bool,
+    body: Vec<u8>,
+}
+
+impl QrImage {
+    /// Encodes `data` as a qr code in the requested format
+    pub fn render(data: &str, name: &str, options: &QrOptions) -> AppResult<Self> {
+        if !QR_SIZE.contains(&options.size) {
+            return Err(AppError::ValidationError(format!(
+                "The size has to be between {} and {} pixels",
+                QR_SIZE.start(),
+                QR_SIZE.end()
+            )));
+        }
+        let code = QrCode::with_error_correction_level(data, options.ec.into()).map_err(|e| {
+            AppError::ValidationError(format!("Unable to encode the url as a qr code: {e}"))
+        })?;
+
+        let body = match options.format {
+            QrFormat::Svg => {
+                let fg = options.fg.to_string();
+                let bg = options.bg.to_string();
+                let image = code
+                    .render()
+                    .min_dimensions(options.size, options.size)
+                    .dark_color(svg::Color(&fg))
+                    .light_color(svg::Color(&bg))
+                    .build();
+                image.into_bytes()
+            }
+            QrFormat::Png => render_png(&code, options)?,
+        };
+
+        Ok(Self {
+            format: options.format,
+            name: name.to_string(),
+            download: options.download,
+            body,
+        })
+    }
+}
+
+/// Draws the modules into an rgb image, qrcode's own png support would pull in the image crate
+fn render_png(code: &QrCode, options: &QrOptions) -> AppResult<Vec<u8>> {
+    let modules = code.width() as u32;
+    let total = modules + 2 * QUIET_ZONE_MODULES;
+    let scale = options.size.div_ceil(total);
+    let side = total * scale;
+    let colors = code.to_colors();
+
+    let mut pixels = Vec::with_capacity((side * side * 3) as usize);
+    for y in 0..side {
+        for x in 0..side {
+            let (mx, my) = (x / scale, y / scale);
+            let dark = (QUIET_ZONE_MODULES..QUIET_ZONE_MODULES + modules).contains(&mx)
+                && (QUIET_ZONE_MODULES..QUIET_ZONE_MODULES + modules).contains(&my)
+                && colors[((my - QUIET_ZONE_MODULES) * modules + mx - QUIET_ZONE_MODULES) as usize]
+                    == qrcode::Color::Dark;
+            let color = if dark { options.fg } else { options.bg };
+            pixels.extend_from_slice(&color.0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, side, side);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))?;
+    Ok(png)
+}
+
+impl IntoResponse for QrImage {
+    fn into_response(self) -> Response {
+        let disposition = format!(
+            "{}; filename=\"{}-qr.{}\"",
+            if self.download {
+                "attachment"
+            } else {
+                "inline"
+            },
+            self.name,
+            self.format.extension()
+        );
+        (
+            [
+                (header::CONTENT_TYPE, self.format.content_type().to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+            ],
+            self.body,
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(query: &str) -> QrOptions {
+        serde_urlencoded::from_str(query).expect("valid options")
+    }
+
+    #[test]
+    fn parses_hex_colours() {
+        assert_eq!("1a2B3c".parse(), Ok(Color([0x1a, 0x2b, 0x3c])));
+        assert_eq!(" #ffffff ".parse(), Ok(Color::WHITE));
+        assert_eq!(Color([0x1a, 0x2b, 0x3c]).to_string(), "#1a2b3c");
+        for invalid in ["", "fff", "1234567", "gg0000", "#12345", "ééé"] {
+            assert!(invalid.parse::<Color>().is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn options_default_to_a_black_on_white_png() {
+        let defaults = options("");
+        assert_eq!(defaults.format, QrFormat::Png);
+        assert_eq!(defaults.size, 256);
+        assert_eq!(defaults.fg, Color::BLACK);
+        assert_eq!(defaults.bg, Color::WHITE);
+        assert!(serde_urlencoded::from_str::<QrOptions>("fg=red").is_err());
+    }
+
+    #[test]
+    fn sizes_outside_the_bounds_are_rejected() {
+        for size in [0, *QR_SIZE.start() - 1, *QR_SIZE.end() + 1] {
+            let result = QrImage::render(
+                "https://sho.rt/abc",
+                "abc",
+                &options(&format!("size={size}")),
+            );
+            assert!(
+                matches!(result, Err(AppError::ValidationError(_))),
+                "{size} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn pngs_are_at_least_the_requested_size() {
+        for size in [*QR_SIZE.start(), 300, *QR_SIZE.end()] {
+            let image = QrImage::render(
+                "https://sho.rt/abc",
+                "abc",
+                &options(&format!("size={size}")),
+            )
+            .expect("a qr code");
+            let decoder = png::Decoder::new(std::io::Cursor::new(image.body));
+            let info = decoder.read_info().expect("a valid png").info().clone();
+            assert_eq!(info.width, info.height);
+            assert!(info.width >= size, "{} < {size}", info.width);
+        }
+    }
+
+    #[test]
+    fn svgs_use_the_requested_colours() {
+        let image = QrImage::render(
+            "https://sho.rt/abc",
+            "abc",
+            &options("format=svg&fg=123456&bg=%23abcdef"),
+        )
+        .expect("a qr code");
+        let svg = String::from_utf8(image.body).expect("utf8");
+        assert!(svg.contains("#123456"));
+        assert!(svg.contains("#abcdef"));
+    }
+}
@@ -0,0 +1,335 @@
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt, str::FromStr};
+use url::{Url, form_urlencoded};
+
+/// Longest time browsers and proxies may remember a permanent redirect, so edits still
+/// reach everyone eventually
+const PERMANENT_MAX_AGE_SECS: i64 = 24 * 60 * 60;
+
+/// The status code a short url redirects with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(try_from = "u16", into = "u16")]
+#[repr(i32)]
+pub enum RedirectType {
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+}
+
+impl RedirectType {
+    pub const ALL: [RedirectType; 5] = [
+        Self::MovedPermanently,
+        Self::Found,
+        Self::SeeOther,
+        Self::TemporaryRedirect,
+        Self::PermanentRedirect,
+    ];
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(*self as u16).expect("redirect types are valid status codes")
+    }
+
+    /// Permanent redirects are remembered by browsers and search engines
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::MovedPermanently | Self::PermanentRedirect)
+    }
+}
+
+impl From<RedirectType> for u16 {
+    fn from(redirect_type: RedirectType) -> Self {
+        redirect_type as u16
+    }
+}
+
+impl TryFrom<u16> for RedirectType {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|t| *t as u16 == code)
+            .ok_or_else(|| {
+                format!("{code} is not a supported redirect, use one of 301, 302, 303, 307, 308")
+            })
+    }
+}
+
+impl FromStr for RedirectType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: u16 = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("{s} is not a status code"))?;
+        code.try_into()
+    }
+}
+
+impl fmt::Display for RedirectType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", *self as u16)
+    }
+}
+
+/// Everything needed to answer a visit of a short url, this is what gets cached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectTarget {
+    pub url: String,
+    pub redirect_type: RedirectType,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Click limited links have to be counted on every visit
+    pub click_limited: bool,
+    /// Whether the query string and extra path of a visit are forwarded
+    pub passthrough: bool,
+}
+
+impl RedirectTarget {
+    /// Appends the extra `path` of a visit to the destination path and merges its `query`
+    /// into the destination query.
+    ///
+    /// `.` and `..` segments of the path are dropped, so a visit can't reach anything outside
+    /// the destination path.
+    ///
+    /// Parameters the destination already sets win, the visitor's value for them is dropped so
+    /// links can't be tricked into e.g. a different campaign. Both are expected to be still
+    /// percent encoded, as they were in the request.
+    pub fn forward(mut self, path: Option<&str>, query: Option<&str>) -> Self {
+        let Ok(mut url) = Url::parse(&self.url) else {
+            return self;
+        };
+        // urls like mailto: have no path segments to append to
+        if url.cannot_be_a_base() {
+            return self;
+        }
+
+        if let Some(path) = path
+            .map(without_dot_segments)
+            .filter(|path| !path.is_empty())
+        {
+            let base = url.path().trim_end_matches('/').to_string();
+            url.set_path(&format!("{base}/{path}"));
+        }
+
+        if let Some(query) = query.filter(|query| !query.is_empty()) {
+            let mut merged = url.query().unwrap_or_default().to_string();
+            let taken: HashSet<String> = form_urlencoded::parse(merged.as_bytes())
+                .map(|(key, _)| key.into_owned())
+                .collect();
+            for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+                let key = form_urlencoded::parse(pair.as_bytes())
+                    .next()
+                    .map(|(key, _)| key.into_owned())
+                    .unwrap_or_default();
+                if taken.contains(&key) {
+                    continue;
+                }
+                if !merged.is_empty() {
+                    merged.push('&');
+                }
+                merged.push_str(pair);
+            }
+            url.set_query(Some(&merged));
+        }
+
+        self.url = url.into();
+        self
+    }
+
+    /// Permanent redirects may be cached for a while, but never past the expiry of the link.
+    /// Everything else has to come back to us, so each click gets counted.
+    fn cache_control(&self) -> String {
+        if !self.redirect_type.is_permanent() || self.click_limited {
+            return "private, no-cache".to_string();
+        }
+        let max_age = match self.expires_at {
+            Some(expires_at) => (expires_at - Utc::now())
+                .num_seconds()
+                .clamp(0, PERMANENT_MAX_AGE_SECS),
+            None => PERMANENT_MAX_AGE_SECS,
+        };
+        format!("public, max-age={max_age}")
+    }
+}
+
+/// `path` without the segments that would be resolved against the ones before it. Backslashes
+/// separate segments too, urls treat them like slashes.
+fn without_dot_segments(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|segment| {
+            let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+            segment != "." && segment != ".."
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl IntoResponse for RedirectTarget {
+    fn into_response(self) -> Response {
+        let cache_control = self.cache_control();
+        (
+            self.redirect_type.status(),
+            [
+                (header::LOCATION, self.url),
+                (header::CACHE_CONTROL, cache_control),
+            ],
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn target(redirect_type: RedirectType) -> RedirectTarget {
+        RedirectTarget {
+            url: "https://example.com/".to_string(),
+            redirect_type,
+            expires_at: None,
+            click_limited: false,
+            passthrough: false,
+        }
+    }
+
+    #[test]
+    fn parses_supported_status_codes() {
+        assert_eq!("301".parse(), Ok(RedirectType::MovedPermanently));
+        assert_eq!(" 307 ".parse(), Ok(RedirectType::TemporaryRedirect));
+        assert!("200".parse::<RedirectType>().is_err());
+        assert!("found".parse::<RedirectType>().is_err());
+        for redirect_type in RedirectType::ALL {
+            assert_eq!(
+                redirect_type.to_string().parse(),
+                Ok(redirect_type),
+                "{redirect_type} doesn't round trip"
+            );
+            assert!(redirect_type.status().is_redirection());
+        }
+    }
+
+    #[test]
+    fn deserializes_from_numbers_only() {
+        assert_eq!(
+            serde_json::from_str::<RedirectType>("308").unwrap(),
+            RedirectType::PermanentRedirect
+        );
+        assert!(serde_json::from_str::<RedirectType>("304").is_err());
+        assert!(serde_json::from_str::<RedirectType>("\"301\"").is_err());
+    }
+
+    #[test]
+    fn only_permanent_redirects_may_be_cached() {
+        assert_eq!(
+            target(RedirectType::MovedPermanently).cache_control(),
+            format!("public, max-age={PERMANENT_MAX_AGE_SECS}")
+        );
+        for redirect_type in [
+            RedirectType::Found,
+            RedirectType::SeeOther,
+            RedirectType::TemporaryRedirect,
+        ] {
+            assert_eq!(target(redirect_type).cache_control(), "private, no-cache");
+        }
+    }
+
+    #[test]
+    fn permanent_redirects_are_not_cached_past_what_limits_them() {
+        let mut expiring = target(RedirectType::PermanentRedirect);
+        expiring.expires_at = Some(Utc::now() + TimeDelta::minutes(10));
+        let max_age: i64 = expiring
+            .cache_control()
+            .strip_prefix("public, max-age=")
+            .and_then(|secs| secs.parse().ok())
+            .expect("a max age");
+        assert!((590..=600).contains(&max_age), "{max_age}");
+
+        expiring.expires_at = Some(Utc::now() - TimeDelta::minutes(10));
+        assert_eq!(expiring.cache_control(), "public, max-age=0");
+
+        let mut counted = target(RedirectType::MovedPermanently);
+        counted.click_limited = true;
+        assert_eq!(counted.cache_control(), "private, no-cache");
+    }
+
+    fn forwarded(url: &str, path: Option<&str>, query: Option<&str>) -> String {
+        let mut target = target(RedirectType::Found);
+        target.url = url.to_string();
+        target.forward(path, query).url
+    }
+
+    #[test]
+    fn appends_the_extra_path() {
+        assert_eq!(
+            forwarded("https://example.com/docs/", Some("guide/intro"), None),
+            "https://example.com/docs/guide/intro"
+        );
+        assert_eq!(
+            forwarded("https://example.com", Some("a%20b"), None),
+            "https://example.com/a%20b"
+        );
+        assert_eq!(
+            forwarded("https://example.com/docs", Some(""), None),
+            "https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn merges_the_query_and_the_destination_wins() {
+        assert_eq!(
+            forwarded(
+                "https://example.com/?utm_source=news",
+                None,
+                Some("utm_source=evil&ref=tw&q=a%26b")
+            ),
+            "https://example.com/?utm_source=news&ref=tw&q=a%26b"
+        );
+        assert_eq!(
+            forwarded("https://example.com/page", None, Some("x=1&&y")),
+            "https://example.com/page?x=1&y"
+        );
+    }
+
+    #[test]
+    fn keeps_destinations_without_a_path_as_they_are() {
+        assert_eq!(
+            forwarded("mailto:someone@example.com", Some("x"), Some("subject=hi")),
+            "mailto:someone@example.com"
+        );
+    }
+
+    #[test]
+    fn visits_cannot_leave_the_destination_path() {
+        for path in [
+            "../../admin",
+            "a/../../admin",
+            "./../admin",
+            "%2e%2e/%2E%2E/admin",
+            ".%2e/admin",
+            "..\\..\\admin",
+        ] {
+            let url = forwarded("https://example.com/docs/", Some(path), None);
+            assert!(
+                url.starts_with("https://example.com/docs/"),
+                "{path}: {url}"
+            );
+            assert!(url.ends_with("/admin"), "{path}: {url}");
+        }
+        assert_eq!(
+            forwarded("https://example.com/docs", Some("a/./b/../c"), None),
+            "https://example.com/docs/a/b/c"
+        );
+        assert_eq!(
+            forwarded("https://example.com/docs", Some("../.."), None),
+            "https://example.com/docs"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Deserializer};
+use std::{fmt::Display, str::FromStr};
+
+/// Html forms send empty inputs as empty strings, this treats them as missing instead.
+///
+/// Use with `#[serde(default, deserialize_with = "empty_string_as_none")]`
+pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = Option::<String>::deserialize(de)?;
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => s.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Tells a json `null` apart from a missing field, `Some(None)` means the field was `null`.
+///
+/// Use with `#[serde(default, deserialize_with = "double_option")]`
+pub fn double_option<'de, D, T>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
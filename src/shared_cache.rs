@@ -0,0 +1,799 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use redis::{
+    Cmd, ErrorKind, Expiry, FromRedisValue, RedisError, RedisResult,
+    aio::{ConnectionManager, ConnectionManagerConfig, PubSubStream},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    fmt,
+    marker::PhantomData,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    cache::{CacheBackend, TtlCache, write_metric},
+    config::Config,
+};
+
+/// Prepended to every key, so the cache can share a redis database with other applications
+const KEY_PREFIX: &str = "yaus";
+
+/// How long a redis command may take before the lookup goes to the database instead
+const REDIS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long redis is left alone after it couldn't be reached, so every lookup doesn't wait
+/// for the timeout while it's down
+const REDIS_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// How often the in memory caches drop expired entries
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where the redirect caches are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheBackendKind {
+    /// In the memory of this instance
+    #[default]
+    Local,
+    /// In redis, or anything speaking its protocol, shared by every instance
+    Redis,
+    /// A small cache in the memory of this instance, in front of redis
+    Tiered,
+}
+
+impl FromStr for CacheBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "redis" => Ok(Self::Redis),
+            "tiered" => Ok(Self::Tiered),
+            other => Err(format!(
+                "unknown cache backend {other}, expected one of local, redis, tiered"
+            )),
+        }
+    }
+}
+
+/// The connection shared by every redis cache, it reconnects by itself after failures
+#[derive(Clone)]
+pub struct RedisConnection {
+    client: redis::Client,
+    manager: ConnectionManager,
+    /// Set after a connection failure, commands fail right away until then
+    unavailable_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RedisConnection {
+    pub async fn connect(url: &str) -> RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        // the manager retries for a good while before giving up, fail right away at startup
+        tokio::time::timeout(REDIS_TIMEOUT, client.get_multiplexed_async_connection())
+            .await
+            .unwrap_or_else(|_| Err((ErrorKind::IoError, "redis did not answer in time").into()))?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT)
+            .set_max_delay(REDIS_RETRY_AFTER.as_millis() as u64);
+        let manager = ConnectionManager::new_with_config(client.clone(), config).await?;
+        Ok(Self {
+            client,
+            manager,
+            unavailable_until: Arc::default(),
+        })
+    }
+
+    /// Runs a command, giving up after [`REDIS_TIMEOUT`] even while the manager is reconnecting
+    async fn run<T: FromRedisValue>(&self, command: Cmd) -> RedisResult<T> {
+        let unavailable_until = *self
+            .unavailable_until
+            .lock()
+            .expect("redis availability lock is poisoned");
+        if unavailable_until.is_some_and(|until| Instant::now() < until) {
+            return Err((ErrorKind::IoError, "redis is unavailable, retrying shortly").into());
+        }
+
+        let mut manager = self.manager.clone();
+        let result = tokio::time::timeout(REDIS_TIMEOUT, command.query_async(&mut manager))
+            .await
+            .unwrap_or_else(|_| Err((ErrorKind::IoError, "redis did not answer in time").into()));
+        if let Err(e) = &result
+            && (e.is_io_error() || e.is_timeout() || e.is_connection_dropped())
+        {
+            tracing::warn!(
+                "Redis is unavailable, trying again in {}s: {e}",
+                REDIS_RETRY_AFTER.as_secs()
+            );
+            *self
+                .unavailable_until
+                .lock()
+                .expect("redis availability lock is poisoned") =
+                Some(Instant::now() + REDIS_RETRY_AFTER);
+        }
+        result
+    }
+
+    /// Sends `message` to everyone subscribed to `channel`
+    pub async fn publish(&self, channel: &str, message: &str) -> RedisResult<()> {
+        self.run::<()>(Cmd::publish(channel, message)).await
+    }
+
+    /// Opens a separate connection that receives the messages published to `channel`, the
+    /// stream ends when the connection is lost
+    pub async fn subscribe(&self, channel: &str) -> RedisResult<PubSubStream> {
+        let mut pubsub = tokio::time::timeout(REDIS_TIMEOUT, self.client.get_async_pubsub())
+            .await
+            .unwrap_or_else(|_| Err((ErrorKind::IoError, "redis did not answer in time").into()))?;
+        pubsub.subscribe(channel).await?;
+        Ok(pubsub.into_on_message())
+    }
+}
+
+impl fmt::Debug for RedisConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisConnection")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why the caches couldn't be set up at startup
+#[derive(Debug, thiserror::Error)]
+pub enum CacheSetupError {
+    #[error("REDIS_URL must be set for the redis and tiered cache backends")]
+    NoRedis,
+    #[error("Unable to connect to redis: {0}")]
+    Connect(#[from] RedisError),
+}
+
+/// Opens the redis connection the `backend` needs, if it needs one
+pub async fn connect_redis(
+    backend: CacheBackendKind,
+    url: Option<&str>,
+) -> Result<Option<RedisConnection>, CacheSetupError> {
+    match backend {
+        CacheBackendKind::Local => Ok(None),
+        CacheBackendKind::Redis | CacheBackendKind::Tiered => {
+            let url = url.ok_or(CacheSetupError::NoRedis)?;
+            Ok(Some(RedisConnection::connect(url).await?))
+        }
+    }
+}
+
+/// Creates a cache of the configured kind, plus the cleaner of its in memory part if it has one.
+///
+/// `namespace` keeps the keys of the caches apart in redis, `ttl` is how long an entry is kept
+/// after it was last read.
+pub async fn build_cache<V>(
+    config: &Config,
+    redis: Option<&RedisConnection>,
+    namespace: &str,
+    ttl: Duration,
+) -> Result<(Arc<dyn CacheBackend<V>>, Option<JoinHandle<()>>), CacheSetupError>
+where
+    V: Serialize + DeserializeOwned + fmt::Debug + Clone + Send + Sync + 'static,
+{
+    let shared = || {
+        let connection = redis.ok_or(CacheSetupError::NoRedis)?.clone();
+        Ok::<_, CacheSetupError>(RedisCache::new(connection, namespace, ttl))
+    };
+    Ok(match config.cache_backend {
+        CacheBackendKind::Local => {
+            let (cache, cleaner) =
+                TtlCache::new(ttl, CLEANUP_INTERVAL, config.cache_capacity).await;
+            (Arc::new(cache), Some(cleaner))
+        }
+        CacheBackendKind::Redis => (Arc::new(shared()?), None),
+        CacheBackendKind::Tiered => {
+            let shared = shared()?;
+            let (local, cleaner) =
+                TtlCache::new(ttl, CLEANUP_INTERVAL, config.local_cache_capacity).await;
+            let cache = TieredCache {
+                local,
+                shared,
+                local_ttl: config.local_cache_ttl,
+            };
+            (Arc::new(cache), Some(cleaner))
+        }
+    })
+}
+
+/// What is stored in redis for every key
+#[derive(Serialize, Deserialize)]
+struct RedisEntry<V> {
+    value: V,
+    /// Unix time in millis the value must not be served after
+    deadline: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+struct RedisMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// A cache kept in redis, shared by every instance using the same server.
+///
+/// Values are stored as json with a sliding ttl, redis drops them once it runs out. When redis
+/// can't be reached lookups are treated as misses, so redirects keep working from the database.
+pub struct RedisCache<V> {
+    connection: RedisConnection,
+    namespace: String,
+    ttl: Duration,
+    metrics: Arc<RedisMetrics>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<V> fmt::Debug for RedisCache<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisCache")
+            .field("namespace", &self.namespace)
+            .field("ttl", &self.ttl)
+            .field("metrics", &self.metrics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> RedisCache<V> {
+    pub fn new(connection: RedisConnection, namespace: &str, ttl: Duration) -> Self {
+        Self {
+            connection,
+            namespace: namespace.to_string(),
+            ttl,
+            metrics: Arc::default(),
+            _value: PhantomData,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{KEY_PREFIX}:{}:{key}", self.namespace)
+    }
+
+    fn failed(&self, action: &str, e: impl fmt::Display) {
+        self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        // connection failures are already logged once per outage
+        tracing::debug!(
+            "Failed to {action} the {} cache in redis: {e}",
+            self.namespace
+        );
+    }
+
+    /// Looks up a value and slides its ttl, along with its deadline if it has one
+    async fn get_entry(&self, key: &str) -> Option<(V, Option<Instant>)> {
+        let key = self.key(key);
+        let ttl = self.ttl.as_millis() as u64;
+        let raw: Option<String> = match self
+            .connection
+            .run(Cmd::get_ex(&key, Expiry::PX(ttl)))
+            .await
+        {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.failed("read", e);
+                return None;
+            }
+        };
+        let Some(raw) = raw else {
+            self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let entry: RedisEntry<V> = match serde_json::from_str(&raw) {
+            Ok(entry) => entry,
+            Err(e) => {
+                // written by a different version, it's replaced on the next insert
+                self.failed("decode", e);
+                return None;
+            }
+        };
+
+        let deadline = match entry.deadline {
+            Some(deadline) => {
+                let remaining = deadline - Utc::now().timestamp_millis();
+                if remaining <= 0 {
+                    self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                // sliding the ttl may have moved the expiry past the deadline
+                if (remaining as u64) < ttl
+                    && let Err(e) = self
+                        .connection
+                        .run::<bool>(Cmd::pexpire_at(&key, deadline))
+                        .await
+                {
+                    self.failed("expire", e);
+                }
+                Some(Instant::now() + Duration::from_millis(remaining as u64))
+            }
+            None => None,
+        };
+        self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+        Some((entry.value, deadline))
+    }
+
+    async fn put(&self, key: &str, value: V, deadline: Option<Instant>) {
+        let now = Instant::now();
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(now));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return;
+        }
+        let ttl = remaining.map_or(self.ttl, |remaining| remaining.min(self.ttl));
+        let entry = RedisEntry {
+            value,
+            deadline: remaining
+                .map(|remaining| Utc::now().timestamp_millis() + remaining.as_millis() as i64),
+        };
+        let raw = match serde_json::to_string(&entry) {
+            Ok(raw) => raw,
+            Err(e) => return self.failed("encode", e),
+        };
+        let command = Cmd::pset_ex(self.key(key), raw, ttl.as_millis().max(1) as u64);
+        if let Err(e) = self.connection.run::<()>(command).await {
+            self.failed("write", e);
+        }
+    }
+}
+
+#[async_trait]
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> CacheBackend<V> for RedisCache<V> {
+    async fn get(&self, key: &str) -> Option<V> {
+        self.get_entry(key).await.map(|(value, _)| value)
+    }
+
+    async fn insert(&self, key: String, value: V) {
+        self.put(&key, value, None).await;
+    }
+
+    async fn insert_until(&self, key: String, value: V, deadline: Instant) {
+        self.put(&key, value, Some(deadline)).await;
+    }
+
+    async fn remove(&self, key: &str) {
+        if let Err(e) = self.connection.run::<()>(Cmd::del(self.key(key))).await {
+            self.failed("remove from", e);
+        }
+    }
+
+    async fn evict_local(&self, _key: &str) {
+        // nothing is kept in memory, redis already has the change
+    }
+
+    async fn write_metrics(&self, prefix: &str, out: &mut String) {
+        let metrics = [
+            (
+                "hits_total",
+                "Lookups answered from redis",
+                &self.metrics.hits,
+            ),
+            (
+                "misses_total",
+                "Lookups that had to go to the database",
+                &self.metrics.misses,
+            ),
+            (
+                "errors_total",
+                "Redis commands that failed, lookups went to the database instead",
+                &self.metrics.errors,
+            ),
+        ];
+        for (name, help, value) in metrics {
+            write_metric(
+                out,
+                prefix,
+                name,
+                "counter",
+                help,
+                value.load(Ordering::Relaxed),
+            );
+        }
+    }
+}
+
+/// A small in memory cache in front of redis, for the links visited most on this instance.
+///
+/// Entries are only kept locally for `local_ttl`, however often they are read, since changes
+/// made through other instances only reach redis.
+#[derive(Debug)]
+pub struct TieredCache<V> {
+    local: TtlCache<V>,
+    shared: RedisCache<V>,
+    local_ttl: Duration,
+}
+
+impl<V> TieredCache<V> {
+    fn local_deadline(&self, deadline: Option<Instant>) -> Instant {
+        let local = Instant::now() + self.local_ttl;
+        deadline.map_or(local, |deadline| deadline.min(local))
+    }
+}
+
+#[async_trait]
+impl<V> CacheBackend<V> for TieredCache<V>
+where
+    V: Serialize + DeserializeOwned + fmt::Debug + Clone + Send + Sync + 'static,
+{
+    async fn get(&self, key: &str) -> Option<V> {
+        if let Some(value) = self.local.get(key).await {
+            return Some(value);
+        }
+        let (value, deadline) = self.shared.get_entry(key).await?;
+        self.local
+            .insert_until(
+                key.to_string(),
+                value.clone(),
+                self.local_deadline(deadline),
+            )
+            .await;
+        Some(value)
+    }
+
+    async fn insert(&self, key: String, value: V) {
+        self.local
+            .insert_until(key.clone(), value.clone(), self.local_deadline(None))
+            .await;
+        self.shared.insert(key, value).await;
+    }
+
+    async fn insert_until(&self, key: String, value: V, deadline: Instant) {
+        self.local
+            .insert_until(
+                key.clone(),
+                value.clone(),
+                self.local_deadline(Some(deadline)),
+            )
+            .await;
+        self.shared.insert_until(key, value, deadline).await;
+    }
+
+    async fn remove(&self, key: &str) {
+        self.local.remove(key).await;
+        self.shared.remove(key).await;
+    }
+
+    async fn evict_local(&self, key: &str) {
+        self.local.remove(key).await;
+    }
+
+    async fn write_metrics(&self, prefix: &str, out: &mut String) {
+        self.local.write_metrics(prefix, out).await;
+        self.shared
+            .write_metrics(&format!("{prefix}_shared"), out)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, net::SocketAddr, num::NonZeroUsize, sync::atomic::AtomicBool};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// Speaks just enough of the redis protocol for the caches, keeping keys in memory and
+    /// recording every command it gets
+    #[derive(Clone, Default)]
+    struct FakeRedis {
+        /// Value and unix time in millis it expires at, for every key
+        keys: Arc<Mutex<HashMap<String, (String, i64)>>>,
+        commands: Arc<Mutex<Vec<Vec<String>>>>,
+        /// Commands are still read and recorded, but never answered
+        stalled: Arc<AtomicBool>,
+    }
+
+    impl FakeRedis {
+        async fn start() -> (Self, RedisConnection) {
+            let fake = Self::default();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr: SocketAddr = listener.local_addr().unwrap();
+            let server = fake.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(server.clone().serve(stream));
+                }
+            });
+            let connection = RedisConnection::connect(&format!("redis://{addr}"))
+                .await
+                .unwrap();
+            fake.commands.lock().unwrap().clear();
+            (fake, connection)
+        }
+
+        async fn serve(self, stream: TcpStream) {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            while let Some(command) = read_command(&mut reader).await {
+                self.commands.lock().unwrap().push(command.clone());
+                if self.stalled.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let answer = self.answer(&command);
+                if writer.write_all(answer.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        fn answer(&self, command: &[String]) -> String {
+            let now = Utc::now().timestamp_millis();
+            let mut keys = self.keys.lock().unwrap();
+            keys.retain(|_, (_, expires_at)| *expires_at > now);
+            match command[0].to_uppercase().as_str() {
+                "GETEX" => match keys.get_mut(&command[1]) {
+                    Some((value, expires_at)) => {
+                        if let Some(ttl) = command.get(3) {
+                            *expires_at = now + ttl.parse::<i64>().unwrap();
+                        }
+                        format!("${}\r\n{value}\r\n", value.len())
+                    }
+                    None => "$-1\r\n".to_string(),
+                },
+                "PSETEX" => {
+                    let ttl: i64 = command[2].parse().unwrap();
+                    keys.insert(command[1].clone(), (command[3].clone(), now + ttl));
+                    "+OK\r\n".to_string()
+                }
+                "PEXPIREAT" => match keys.get_mut(&command[1]) {
+                    Some((_, expires_at)) => {
+                        *expires_at = command[2].parse().unwrap();
+                        ":1\r\n".to_string()
+                    }
+                    None => ":0\r\n".to_string(),
+                },
+                "DEL" => {
+                    let removed = command[1..]
+                        .iter()
+                        .filter(|key| keys.remove(*key).is_some())
+                        .count();
+                    format!(":{removed}\r\n")
+                }
+                // the handshake, CLIENT SETINFO and the like
+                _ => "+OK\r\n".to_string(),
+            }
+        }
+
+        fn commands(&self) -> Vec<Vec<String>> {
+            std::mem::take(&mut *self.commands.lock().unwrap())
+        }
+
+        fn key(&self, key: &str) -> Option<(String, i64)> {
+            self.keys.lock().unwrap().get(key).cloned()
+        }
+    }
+
+    /// Reads one command, sent as an array of bulk strings
+    async fn read_command<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut command = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            command.push(String::from_utf8(arg).ok()?);
+        }
+        Some(command)
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    async fn tiered(connection: RedisConnection) -> TieredCache<u32> {
+        let (local, cleaner) = TtlCache::new(
+            TTL,
+            Duration::from_secs(3600),
+            NonZeroUsize::new(10).unwrap(),
+        )
+        .await;
+        cleaner.abort();
+        TieredCache {
+            local,
+            shared: RedisCache::new(connection, "link", TTL),
+            local_ttl: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn backend_kinds_are_parsed() {
+        assert_eq!("local".parse(), Ok(CacheBackendKind::Local));
+        assert_eq!("redis".parse(), Ok(CacheBackendKind::Redis));
+        assert_eq!("tiered".parse(), Ok(CacheBackendKind::Tiered));
+        assert!("memcached".parse::<CacheBackendKind>().is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_is_an_error() {
+        // nothing listens on a port that was just freed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let error = RedisConnection::connect(&format!("redis://{addr}"))
+            .await
+            .map(|_| ())
+            .map_err(CacheSetupError::from)
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Unable to connect to redis"));
+    }
+
+    #[tokio::test]
+    async fn redis_backends_need_a_redis_url() {
+        assert!(matches!(
+            connect_redis(CacheBackendKind::Local, None).await,
+            Ok(None)
+        ));
+        for backend in [CacheBackendKind::Redis, CacheBackendKind::Tiered] {
+            assert!(matches!(
+                connect_redis(backend, None).await,
+                Err(CacheSetupError::NoRedis)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn values_are_stored_as_json_with_a_ttl() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = RedisCache::new(connection, "link", TTL);
+
+        cache.insert("abc".to_string(), 7u32).await;
+        assert_eq!(
+            fake.commands(),
+            [command(&[
+                "PSETEX",
+                "yaus:link:abc",
+                "60000",
+                r#"{"value":7,"deadline":null}"#
+            ])]
+        );
+        assert_eq!(cache.get("abc").await, Some(7));
+        assert_eq!(cache.get("other").await, None);
+    }
+
+    #[tokio::test]
+    async fn reads_slide_the_ttl() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = RedisCache::new(connection, "link", TTL);
+        cache.insert("abc".to_string(), 7u32).await;
+        let (_, first_expiry) = fake.key("yaus:link:abc").unwrap();
+        fake.commands();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.get("abc").await, Some(7));
+        assert_eq!(
+            fake.commands(),
+            [command(&["GETEX", "yaus:link:abc", "PX", "60000"])]
+        );
+        let (_, expiry) = fake.key("yaus:link:abc").unwrap();
+        assert!(expiry > first_expiry);
+    }
+
+    #[tokio::test]
+    async fn deadlines_are_stored_with_the_value() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = RedisCache::new(connection, "link", TTL);
+
+        let before = Utc::now().timestamp_millis();
+        cache
+            .insert_until(
+                "abc".to_string(),
+                7u32,
+                Instant::now() + Duration::from_secs(10),
+            )
+            .await;
+        let (raw, expiry) = fake.key("yaus:link:abc").unwrap();
+        let entry: RedisEntry<u32> = serde_json::from_str(&raw).unwrap();
+        let deadline = entry.deadline.unwrap();
+        assert!((before + 9_000..=before + 11_000).contains(&deadline));
+        // redis drops it at the deadline rather than after the full ttl
+        assert!(expiry <= deadline + 100);
+        fake.commands();
+
+        // sliding the ttl on a read pulls the expiry back to the deadline
+        let (value, local_deadline) = cache.get_entry("abc").await.unwrap();
+        assert_eq!(value, 7);
+        assert!(local_deadline.unwrap() <= Instant::now() + Duration::from_secs(10));
+        assert_eq!(
+            fake.commands(),
+            [
+                command(&["GETEX", "yaus:link:abc", "PX", "60000"]),
+                command(&["PEXPIREAT", "yaus:link:abc", &deadline.to_string()]),
+            ]
+        );
+        assert_eq!(fake.key("yaus:link:abc").unwrap().1, deadline);
+    }
+
+    #[tokio::test]
+    async fn values_past_their_deadline_are_misses() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = RedisCache::<u32>::new(connection, "link", TTL);
+        let deadline = Utc::now().timestamp_millis() - 1;
+        fake.keys.lock().unwrap().insert(
+            "yaus:link:abc".to_string(),
+            (format!(r#"{{"value":7,"deadline":{deadline}}}"#), i64::MAX),
+        );
+
+        assert_eq!(cache.get("abc").await, None);
+        assert_eq!(cache.metrics.misses.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.metrics.hits.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn redis_is_left_alone_for_a_while_after_it_failed() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = RedisCache::new(connection, "link", TTL);
+        cache.insert("abc".to_string(), 7u32).await;
+        fake.commands();
+
+        fake.stalled.store(true, Ordering::SeqCst);
+        assert_eq!(cache.get("abc").await, None);
+        assert_eq!(fake.commands().len(), 1);
+
+        // a miss right away, without waiting for redis again
+        let started = Instant::now();
+        assert_eq!(cache.get("abc").await, None);
+        assert!(started.elapsed() < REDIS_TIMEOUT / 2);
+        assert!(fake.commands().is_empty());
+        assert_eq!(cache.metrics.errors.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn tiered_reads_are_answered_locally_first() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = tiered(connection).await;
+        cache.insert("abc".to_string(), 7).await;
+        assert!(fake.key("yaus:link:abc").is_some());
+        fake.commands();
+
+        assert_eq!(cache.get("abc").await, Some(7));
+        assert!(fake.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tiered_misses_are_filled_from_redis() {
+        let (fake, connection) = FakeRedis::start().await;
+        // written by another instance
+        RedisCache::new(connection.clone(), "link", TTL)
+            .insert("abc".to_string(), 7u32)
+            .await;
+        let cache = tiered(connection).await;
+        fake.commands();
+
+        assert_eq!(cache.get("abc").await, Some(7));
+        assert_eq!(fake.commands().len(), 1);
+        assert_eq!(cache.local.get("abc").await, Some(7));
+        assert_eq!(cache.get("abc").await, Some(7));
+        assert!(fake.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tiered_local_evictions_leave_redis_alone() {
+        let (fake, connection) = FakeRedis::start().await;
+        let cache = tiered(connection).await;
+        cache.insert("abc".to_string(), 7).await;
+        fake.commands();
+
+        cache.evict_local("abc").await;
+        assert_eq!(cache.local.get("abc").await, None);
+        assert!(fake.commands().is_empty());
+        assert!(fake.key("yaus:link:abc").is_some());
+
+        cache.remove("abc").await;
+        assert_eq!(fake.commands(), [command(&["DEL", "yaus:link:abc"])]);
+        assert_eq!(fake.key("yaus:link:abc"), None);
+    }
+}
@@ -0,0 +1,330 @@
+use rand::{Rng, seq::SliceRandom};
+use std::{fmt::Debug, str::FromStr, sync::Arc};
+
+/// Creates the short codes for links that don't have a custom alias.
+///
+/// `UrlStore::insert` calls `generate` again with an increased `attempt` whenever the returned
+/// code is already taken, implementations use it to widen the space of possible codes.
+pub trait ShortCodeGenerator: Send + Sync + Debug {
+    /// Whether `generate` needs a fresh number from the persistent sequence for every code
+    fn is_sequential(&self) -> bool {
+        false
+    }
+
+    /// `seq` is a number that was never handed out before (always `0` for non sequential
+    /// generators), `attempt` is the number of collisions so far for this link
+    fn generate(&self, seq: u64, attempt: u32) -> String;
+}
+
+/// Which generator to use, read from `SHORT_CODE_STRATEGY`
+#[derive(Clone, Debug)]
+pub enum ShortCodeStrategy {
+    Nanoid { alphabet: Vec<char>, length: usize },
+    Sequential,
+    Hashids { salt: String, min_length: usize },
+    Words,
+}
+
+impl Default for ShortCodeStrategy {
+    fn default() -> Self {
+        Self::Nanoid {
+            alphabet: nanoid::alphabet::SAFE.to_vec(),
+            length: 8,
+        }
+    }
+}
+
+impl FromStr for ShortCodeStrategy {
+    type Err = String;
+
+    /// Parses the strategy name, the parameters start out with their defaults
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nanoid" => Ok(Self::default()),
+            "sequential" => Ok(Self::Sequential),
+            "hashids" => Ok(Self::Hashids {
+                salt: String::new(),
+                min_length: 6,
+            }),
+            "words" => Ok(Self::Words),
+            other => Err(format!(
+                "unknown short code strategy {other}, expected one of nanoid, sequential, hashids, words"
+            )),
+        }
+    }
+}
+
+impl ShortCodeStrategy {
+    pub fn build(&self) -> Arc<dyn ShortCodeGenerator> {
+        match self {
+            Self::Nanoid { alphabet, length } => {
+                Arc::new(NanoidGenerator::new(alphabet.clone(), *length))
+            }
+            Self::Sequential => Arc::new(SequentialGenerator),
+            Self::Hashids { salt, min_length } => {
+                Arc::new(HashidsGenerator::new(salt, *min_length))
+            }
+            Self::Words => Arc::new(WordPairGenerator),
+        }
+    }
+}
+
+/// Checks a custom nanoid alphabet: codes end up in urls unescaped and take the same characters
+/// as aliases, and repeated characters would make some codes more likely than others
+pub fn parse_alphabet(chars: &str) -> Result<Vec<char>, String> {
+    let alphabet: Vec<char> = chars.chars().collect();
+    if alphabet.len() < 2 {
+        return Err("it needs at least two characters".to_string());
+    }
+    if let Some(c) = alphabet
+        .iter()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_')))
+    {
+        return Err(format!(
+            "{c:?} can't be used, only ascii letters, digits, '-' and '_' can"
+        ));
+    }
+    if let Some((i, c)) = alphabet
+        .iter()
+        .enumerate()
+        .find(|(i, c)| alphabet[..*i].contains(c))
+    {
+        return Err(format!("{c:?} appears more than once (at position {i})"));
+    }
+    Ok(alphabet)
+}
+
+/// Random codes from a configurable alphabet, the length grows by one every two collisions
+#[derive(Debug)]
+pub struct NanoidGenerator {
+    alphabet: Vec<char>,
+    length: usize,
+}
+
+impl NanoidGenerator {
+    pub fn new(alphabet: Vec<char>, length: usize) -> Self {
+        assert!(
+            alphabet.len() >= 2,
+            "the short code alphabet needs at least two characters"
+        );
+        assert!(length > 0, "the short code length can't be 0");
+        Self { alphabet, length }
+    }
+}
+
+impl ShortCodeGenerator for NanoidGenerator {
+    fn generate(&self, _seq: u64, attempt: u32) -> String {
+        let length = self.length + (attempt / 2) as usize;
+        nanoid::format(nanoid::rngs::default, &self.alphabet, length)
+    }
+}
+
+const BASE62: &[u8; 62] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// The shortest possible codes, the sequence number written in base62
+#[derive(Debug)]
+pub struct SequentialGenerator;
+
+impl ShortCodeGenerator for SequentialGenerator {
+    fn is_sequential(&self) -> bool {
+        true
+    }
+
+    fn generate(&self, seq: u64, _attempt: u32) -> String {
+        encode(seq, BASE62, 1)
+    }
+}
+
+/// Sequence numbers scrambled into short, non guessable looking codes.
+///
+/// Works like hashids: the alphabet is shuffled with the salt and the number is run
+/// through a bijection first, so codes stay unique without revealing how many links exist.
+#[derive(Debug)]
+pub struct HashidsGenerator {
+    alphabet: Vec<u8>,
+    multiplier: u64,
+    mask: u64,
+    min_length: usize,
+}
+
+impl HashidsGenerator {
+    /// Numbers below 2^35 get scrambled (~34 billion links), which fits in 6 base62 characters
+    const BITS: u32 = 35;
+
+    pub fn new(salt: &str, min_length: usize) -> Self {
+        let salt_hash = salt.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+        // Fisher-Yates with a deterministic, salt derived "random" sequence
+        let mut alphabet = BASE62.to_vec();
+        let mut state = salt_hash;
+        for i in (1..alphabet.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            alphabet.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+
+        Self {
+            alphabet,
+            // an odd multiplier makes `x * m mod 2^k` a bijection
+            multiplier: (salt_hash & ((1 << Self::BITS) - 1)) | 1,
+            mask: (1 << Self::BITS) - 1,
+            min_length,
+        }
+    }
+}
+
+impl ShortCodeGenerator for HashidsGenerator {
+    fn is_sequential(&self) -> bool {
+        true
+    }
+
+    fn generate(&self, seq: u64, _attempt: u32) -> String {
+        let scrambled = if seq <= self.mask {
+            seq.wrapping_mul(self.multiplier) & self.mask
+        } else {
+            seq
+        };
+        encode(scrambled, &self.alphabet, self.min_length)
+    }
+}
+
+/// Easy to read out loud codes like `brave-otter`, a number is appended after a collision
+#[derive(Debug)]
+pub struct WordPairGenerator;
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "brisk", "calm", "clever", "cosmic", "crisp", "daring", "dusty",
+    "eager", "early", "fancy", "fluffy", "gentle", "giant", "glad", "golden", "happy", "hidden",
+    "humble", "icy", "jolly", "keen", "kind", "lazy", "little", "lively", "lucky", "mellow",
+    "merry", "misty", "noble", "odd", "plain", "polite", "proud", "quick", "quiet", "rapid",
+    "rosy", "royal", "rusty", "shiny", "silent", "silver", "sleepy", "smooth", "snowy", "solid",
+    "sunny", "swift", "tame", "tidy", "tiny", "vivid", "warm", "wild", "windy", "wise", "witty",
+    "young", "zany", "zesty",
+];
+
+const NOUNS: &[&str] = &[
+    "apple", "badger", "bear", "beetle", "canyon", "castle", "cloud", "comet", "coral", "crane",
+    "daisy", "dragon", "eagle", "falcon", "fern", "forest", "fox", "garden", "gecko", "harbor",
+    "hawk", "island", "jaguar", "koala", "lagoon", "lemon", "lion", "lotus", "maple", "meadow",
+    "moon", "moose", "nebula", "ocean", "otter", "owl", "panda", "pebble", "pepper", "pine",
+    "planet", "pony", "quartz", "rabbit", "raven", "river", "robin", "rocket", "salmon", "shadow",
+    "sparrow", "spruce", "storm", "sun", "tiger", "tulip", "turtle", "valley", "walrus", "willow",
+    "wolf", "yak", "zebra", "zephyr",
+];
+
+impl ShortCodeGenerator for WordPairGenerator {
+    fn generate(&self, _seq: u64, attempt: u32) -> String {
+        let mut rng = rand::thread_rng();
+        let adjective = ADJECTIVES.choose(&mut rng).expect("word list is not empty");
+        let noun = NOUNS.choose(&mut rng).expect("word list is not empty");
+        if attempt == 0 {
+            format!("{adjective}-{noun}")
+        } else {
+            // every collision allows one more digit
+            let max = 10_u64.saturating_pow(attempt.min(18));
+            format!("{adjective}-{noun}-{}", rng.gen_range(0..max))
+        }
+    }
+}
+
+/// Writes `n` in base `alphabet.len()`, left padded with the first symbol up to `min_length`
+fn encode(mut n: u64, alphabet: &[u8], min_length: usize) -> String {
+    let base = alphabet.len() as u64;
+    let mut out = Vec::new();
+    loop {
+        out.push(alphabet[(n % base) as usize]);
+        n /= base;
+        if n == 0 {
+            break;
+        }
+    }
+    while out.len() < min_length {
+        out.push(alphabet[0]);
+    }
+    out.reverse();
+    String::from_utf8(out).expect("alphabets are ascii")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn nanoid_codes_use_the_alphabet_and_grow_after_collisions() {
+        let generator = NanoidGenerator::new(vec!['a', 'b'], 5);
+        for (attempt, length) in [(0, 5), (1, 5), (2, 6), (5, 7)] {
+            let code = generator.generate(0, attempt);
+            assert_eq!(code.len(), length, "attempt {attempt}");
+            assert!(code.chars().all(|c| c == 'a' || c == 'b'), "{code}");
+        }
+    }
+
+    #[test]
+    fn sequential_codes_are_the_number_in_base62() {
+        let generator = SequentialGenerator;
+        assert!(generator.is_sequential());
+        assert_eq!(generator.generate(0, 0), "0");
+        assert_eq!(generator.generate(61, 0), "Z");
+        assert_eq!(generator.generate(62, 0), "10");
+        assert_eq!(generator.generate(62 * 62, 0), "100");
+    }
+
+    #[test]
+    fn hashids_codes_are_unique_padded_and_depend_on_the_salt() {
+        let generator = HashidsGenerator::new("pepper", 6);
+        let codes: HashSet<String> = (0..10_000).map(|seq| generator.generate(seq, 0)).collect();
+        assert_eq!(codes.len(), 10_000);
+        assert!(codes.iter().all(|code| code.len() >= 6));
+        // numbers past the scrambled range still get a code of their own
+        assert_ne!(generator.generate(u64::MAX, 0), generator.generate(0, 0));
+
+        let same_salt = HashidsGenerator::new("pepper", 6);
+        let other_salt = HashidsGenerator::new("salt", 6);
+        assert_eq!(generator.generate(42, 0), same_salt.generate(42, 0));
+        assert_ne!(generator.generate(42, 0), other_salt.generate(42, 0));
+    }
+
+    #[test]
+    fn word_codes_get_a_number_after_a_collision() {
+        let generator = WordPairGenerator;
+        let code = generator.generate(0, 0);
+        let (adjective, noun) = code.split_once('-').expect("two words");
+        assert!(ADJECTIVES.contains(&adjective));
+        assert!(NOUNS.contains(&noun));
+
+        let code = generator.generate(0, 2);
+        let number = code.rsplit('-').next().expect("a number");
+        assert!(number.parse::<u64>().is_ok_and(|n| n < 100), "{code}");
+    }
+
+    #[test]
+    fn strategies_parse_by_name() {
+        assert!(matches!(
+            "nanoid".parse(),
+            Ok(ShortCodeStrategy::Nanoid { length: 8, .. })
+        ));
+        assert!(matches!(
+            "sequential".parse(),
+            Ok(ShortCodeStrategy::Sequential)
+        ));
+        assert!("uuid".parse::<ShortCodeStrategy>().is_err());
+    }
+
+    #[test]
+    fn alphabets_must_be_unique_url_safe_characters() {
+        assert_eq!(parse_alphabet("ab-_9"), Ok("ab-_9".chars().collect()));
+        assert!(parse_alphabet("").is_err());
+        assert!(parse_alphabet("a").is_err());
+        assert!(parse_alphabet("abca").is_err());
+        assert!(parse_alphabet("ab/").is_err());
+        assert!(parse_alphabet("ab c").is_err());
+        assert!(parse_alphabet("abé").is_err());
+        // fine in urls, but not in aliases, and `..` is a path segment of its own
+        assert!(parse_alphabet("ab.").is_err());
+        assert!(parse_alphabet("ab~").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Helpers shared by the tests of several modules
+
+use axum::{
+    body::Body,
+    extract::connect_info::MockConnectInfo,
+    http::{Request, header},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{
+    SignedCookieJar,
+    cookie::{Cookie, Key},
+};
+use sqlx::{Pool, Sqlite, sqlite::SqlitePoolOptions};
+use std::{net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+use crate::{
+    AppState,
+    analytics::{ClickEvent, StatsStore},
+    app,
+    cache::TtlCache,
+    config::Config,
+    destination::DestinationPolicy,
+    handlers::auth::SESSION_COOKIE,
+    invalidation::Invalidator,
+    moderation::{Blocklist, ModerationStore},
+    redirect::RedirectType,
+    shared_cache::CacheBackendKind,
+    short_code::ShortCodeStrategy,
+    url_store::{NewShortUrl, UrlStore},
+    user_store::{User, UserStore},
+};
+
+/// A fresh in memory database with every migration applied
+pub async fn pool() -> Pool<Sqlite> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+/// Creates a user on `pool`, the password is not a valid hash so they can't log in
+pub async fn user(pool: &Pool<Sqlite>, email: &str, is_admin: bool) -> User {
+    UserStore::new(pool.clone(), Duration::from_secs(60))
+        .create_user(email, email, "hash", is_admin)
+        .await
+        .unwrap()
+}
+
+/// Creates links with the aliases `shorturls` owned by `owner`, all leading to example.com
+pub async fn links(pool: &Pool<Sqlite>, owner: i64, shorturls: &[&str]) {
+    let (stats_tx, _) = mpsc::channel(1);
+    let blocklist = Blocklist::load(pool.clone(), None).await.unwrap();
+    let store = url_store(pool, stats_tx, blocklist).await;
+    for shorturl in shorturls {
+        let link = NewShortUrl {
+            longurl: format!("https://example.com/{shorturl}"),
+            alias: Some(shorturl.to_string()),
+            ..Default::default()
+        };
+        store.insert(owner, link).await.unwrap();
+    }
+}
+
+/// The defaults `Config::from_env` falls back to, without redis
+pub fn config() -> Config {
+    Config {
+        database_url: "sqlite::memory:".to_string(),
+        public_url: "https://sho.rt".to_string(),
+        session_key: Key::generate(),
+        session_ttl: Duration::from_secs(60 * 60),
+        secure_cookies: false,
+        allow_signup: false,
+        invite_ttl: Duration::from_secs(60 * 60),
+        short_code_strategy: ShortCodeStrategy::default(),
+        ip_hash_salt: "salt".to_string(),
+        trust_proxy_headers: false,
+        extra_url_schemes: Vec::new(),
+        blocklist_file: None,
+        default_redirect: RedirectType::Found,
+        cache_capacity: NonZeroUsize::new(100).unwrap(),
+        missing_cache_ttl: Duration::from_secs(10),
+        cache_backend: CacheBackendKind::Local,
+        redis_url: None,
+        local_cache_capacity: NonZeroUsize::new(10).unwrap(),
+        local_cache_ttl: Duration::from_secs(5),
+        invalidation_poll_interval: Duration::from_millis(10),
+    }
+}
+
+/// A store on `pool` with in memory caches, like one of several instances sharing a database
+pub async fn url_store(
+    pool: &Pool<Sqlite>,
+    stats_tx: mpsc::Sender<ClickEvent>,
+    blocklist: Blocklist,
+) -> UrlStore {
+    let capacity = NonZeroUsize::new(100).unwrap();
+    let (cache, _) =
+        TtlCache::new(Duration::from_secs(60), Duration::from_secs(60), capacity).await;
+    let (missing, _) =
+        TtlCache::new(Duration::from_secs(60), Duration::from_secs(60), capacity).await;
+    UrlStore::new(
+        pool.clone(),
+        Arc::new(cache),
+        Arc::new(missing),
+        ShortCodeStrategy::default().build(),
+        stats_tx,
+        DestinationPolicy::new(&[], "https://sho.rt"),
+        blocklist,
+        RedirectType::Found,
+        Invalidator::change_log(pool.clone(), Duration::from_millis(10)),
+    )
+    .await
+}
+
+/// The state the server runs with, on `pool`. Clicks are dropped.
+pub async fn state(pool: &Pool<Sqlite>) -> AppState {
+    let config = config();
+    let (stats_tx, _) = mpsc::channel(1);
+    let blocklist = Blocklist::load(pool.clone(), None).await.unwrap();
+    AppState {
+        url_store: url_store(pool, stats_tx, blocklist.clone()).await,
+        user_store: UserStore::new(pool.clone(), config.session_ttl),
+        stats_store: StatsStore::new(pool.clone()),
+        moderation: ModerationStore::new(pool.clone()),
+        blocklist,
+        config,
+    }
+}
+
+/// A `Cookie` header value logging in as `user_id`
+pub async fn session_cookie(state: &AppState, user_id: i64) -> String {
+    let session = state.user_store.create_session(user_id).await.unwrap();
+    let jar = SignedCookieJar::new(state.config.session_key.clone())
+        .add(Cookie::new(SESSION_COOKIE, session.id));
+    let response = jar.into_response();
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+/// The body of `response` as text
+pub async fn text_body(response: Response) -> String {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Sends `request` through every route and middleware of the server, as if from localhost
+pub async fn send(state: &AppState, request: Request<Body>) -> Response {
+    app(state.clone())
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))))
+        .oneshot(request)
+        .await
+        .unwrap()
+}
+
+/// The body of `response` parsed as json
+pub async fn json_body(response: Response) -> serde_json::Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
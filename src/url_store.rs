@@ -1,92 +1,1045 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use sqlx::{Pool, Sqlite};
 
-use crate::cache::TtlCache;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use tokio::{
+    sync::{OnceCell, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{
+    analytics::ClickEvent,
+    cache::CacheBackend,
+    destination::DestinationPolicy,
+    errors::{AppError, AppResult},
+    invalidation::Invalidator,
+    moderation::Blocklist,
+    redirect::{RedirectTarget, RedirectType},
+    short_code::ShortCodeGenerator,
+    utm::UtmParams,
+};
+
+/// How often `insert` retries when a generated short url is already taken
+const MAX_GENERATE_ATTEMPTS: u32 = 8;
 
 #[derive(Clone, Debug)]
 pub struct UrlStore {
-    cache: TtlCache,
+    cache: Arc<dyn CacheBackend<RedirectTarget>>,
+    /// Short urls that don't exist
+    missing: Arc<dyn CacheBackend<()>>,
+    /// Database lookups in progress, so concurrent misses of a key wait for the same one
+    loading: Arc<Mutex<HashMap<String, Arc<OnceCell<Lookup>>>>>,
+    /// Bumped by every invalidation. A load that sees it change may have read a short url
+    /// from before the change, so it doesn't cache what it read.
+    generation: Arc<AtomicU64>,
     sqlite_pool: Pool<Sqlite>,
-    //stats_tx: tokio::sync::mpsc::Sender<String>,
+    generator: Arc<dyn ShortCodeGenerator>,
+    stats_tx: mpsc::Sender<ClickEvent>,
+    policy: DestinationPolicy,
+    blocklist: Blocklist,
+    /// Used for links that don't have their own redirect type
+    default_redirect: RedirectType,
+    invalidator: Invalidator,
+}
+
+/// The caches of a [`UrlStore`], for the invalidation listener. It holds only these so it
+/// doesn't keep the click channel open, which the shutdown waits on.
+#[derive(Clone, Debug)]
+pub struct CacheEvictor {
+    cache: Arc<dyn CacheBackend<RedirectTarget>>,
+    missing: Arc<dyn CacheBackend<()>>,
+    generation: Arc<AtomicU64>,
+}
+
+impl CacheEvictor {
+    /// Drops a short url another instance changed from the memory of this one
+    pub async fn evict(&self, shorturl: &str) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.cache.evict_local(shorturl).await;
+        self.missing.evict_local(shorturl).await;
+    }
 }
 
 impl UrlStore {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         sqlite_pool: Pool<Sqlite>,
-        cache: TtlCache,
-        //stats_tx: tokio::sync::mpsc::Sender<String>,
+        cache: Arc<dyn CacheBackend<RedirectTarget>>,
+        missing: Arc<dyn CacheBackend<()>>,
+        generator: Arc<dyn ShortCodeGenerator>,
+        stats_tx: mpsc::Sender<ClickEvent>,
+        policy: DestinationPolicy,
+        blocklist: Blocklist,
+        default_redirect: RedirectType,
+        invalidator: Invalidator,
     ) -> Self {
         UrlStore {
             cache,
+            missing,
+            loading: Arc::default(),
+            generation: Arc::default(),
             sqlite_pool,
-            //stats_tx,
+            generator,
+            stats_tx,
+            policy,
+            blocklist,
+            default_redirect,
+            invalidator,
         }
     }
 
-    pub async fn get(&self, key: String) -> Result<Option<String>, sqlx::Error> {
-        //self.stats_tx.send(key.clone()).await.map_err(|e| e.to_string())?;
-        let value = if let Some(maybe_url) = self.cache.get(&key).await {
-            maybe_url
-        } else {
-            //run db query to get the value
-            let value = if let Some(row) =
-                sqlx::query!("SELECT longurl from shorturls WHERE shorturl = ?", key)
-                    .fetch_optional(&self.sqlite_pool)
-                    .await?
-            {
-                row.longurl
-            } else {
-                return Ok(None);
-            };
+    /// Hands a click to the background recorder, never waits for it.
+    ///
+    /// If the recorder can't keep up the click is dropped, redirects are more important than stats.
+    pub fn record_click(&self, event: ClickEvent) {
+        match self.stats_tx.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(event)) => {
+                tracing::warn!(
+                    "Click channel is full, dropping click on {}",
+                    event.shorturl
+                )
+            }
+            Err(mpsc::error::TrySendError::Closed(event)) => {
+                tracing::error!(
+                    "Click recorder is gone, dropping click on {}",
+                    event.shorturl
+                )
+            }
+        }
+    }
+
+    /// Drops a changed short url from the caches, here and on every other instance
+    async fn invalidate(&self, shorturl: &str) {
+        // before removing, so a load caching a stale entry afterwards notices and drops it again
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        // requests coming in from now on shouldn't wait for a load that started before the change
+        self.loading
+            .lock()
+            .expect("loading lock is poisoned")
+            .remove(shorturl);
+        self.cache.remove(shorturl).await;
+        self.missing.remove(shorturl).await;
+        self.invalidator.publish(shorturl).await;
+    }
+
+    fn evictor(&self) -> CacheEvictor {
+        CacheEvictor {
+            cache: self.cache.clone(),
+            missing: self.missing.clone(),
+            generation: self.generation.clone(),
+        }
+    }
+
+    /// Spawn a background task that evicts the short urls other instances change
+    pub fn spawn_invalidation_listener(&self) -> JoinHandle<()> {
+        self.invalidator.spawn_listener(self.evictor())
+    }
+
+    /// Metrics of the redirect cache and the cache of missing short urls, in the prometheus text format
+    pub async fn write_cache_metrics(&self, out: &mut String) {
+        self.cache.write_metrics("yaus_redirect_cache", out).await;
+        self.missing.write_metrics("yaus_missing_cache", out).await;
+    }
+
+    pub async fn get(&self, key: String) -> AppResult<Option<Destination>> {
+        let lookup = match self.cache.get(&key).await {
+            Some(target) => Lookup::Found(target),
+            None if self.missing.get(&key).await.is_some() => Lookup::Missing,
+            None => self.load_once(&key).await?,
+        };
+        let target = match lookup {
+            Lookup::Found(target) => target,
+            Lookup::Missing => return Ok(None),
+            Lookup::Expired => return Ok(Some(Destination::Expired)),
+            Lookup::Disabled => return Ok(Some(Destination::Disabled)),
+        };
+        // the blocklist can change while the url is cached
+        if self.blocklist.blocked_by(&target.url).is_some() {
+            return Ok(Some(Destination::Disabled));
+        }
+        Ok(Some(Destination::Url(target)))
+    }
+
+    /// Counts a redirected visit to a click limited short url, false once its clicks are used up.
+    ///
+    /// Separate from `get` so a visit that isn't redirected after all doesn't use up a click.
+    pub async fn count_click(&self, key: &str) -> AppResult<bool> {
+        let counted = sqlx::query!(
+            "UPDATE shorturls SET click_count = click_count + 1
+            WHERE shorturl = ? AND click_count < max_clicks",
+            key
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+        Ok(counted.rows_affected() == 1)
+    }
+
+    /// Loads a short url that isn't cached, concurrent calls for the same key share one query
+    async fn load_once(&self, key: &str) -> AppResult<Lookup> {
+        let load = self
+            .loading
+            .lock()
+            .expect("loading lock is poisoned")
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        // if the load fails the next waiter tries again
+        let lookup = load.get_or_try_init(|| self.load(key)).await.cloned();
 
-            //store the values in cache
-            //TODO probably spawn a background task to do this to save some time on the request
-            // actually benchmark to check if its worth it
-            self.cache.insert(key.clone(), value.clone()).await;
-            value
+        // the first one done forgets the load, later requests find the result in the cache
+        let mut loading = self.loading.lock().expect("loading lock is poisoned");
+        if loading
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, &load))
+        {
+            loading.remove(key);
+        }
+        lookup
+    }
+
+    /// Reads a short url from the database and caches what can be cached
+    async fn load(&self, key: &str) -> AppResult<Lookup> {
+        tracing::debug!("Loading {} from the database", key);
+        let generation = self.generation.load(Ordering::SeqCst);
+        let Some(row) = sqlx::query!(
+            r#"SELECT longurl, expires_at as "expires_at: DateTime<Utc>", max_clicks,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough, utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            FROM shorturls WHERE shorturl = ?"#,
+            key
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await?
+        else {
+            // remembered for a short while, so probing random short urls doesn't reach the database
+            self.cache_loaded(&*self.missing, key, (), None, generation)
+                .await;
+            return Ok(Lookup::Missing);
         };
-        //TODO add way to report statistics
-        return Ok(Some(value));
+
+        // disabled links are rare, they are not cached so enabling them again needs no invalidation
+        if row.disabled_at.is_some() || self.blocklist.blocked_by(&row.longurl).is_some() {
+            return Ok(Lookup::Disabled);
+        }
+
+        let now = Utc::now();
+        if row.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Ok(Lookup::Expired);
+        }
+
+        let utm = UtmParams {
+            utm_source: row.utm_source,
+            utm_medium: row.utm_medium,
+            utm_campaign: row.utm_campaign,
+            utm_term: row.utm_term,
+            utm_content: row.utm_content,
+        };
+        let target = RedirectTarget {
+            url: utm.apply(&row.longurl),
+            redirect_type: row.redirect_type.unwrap_or(self.default_redirect),
+            expires_at: row.expires_at,
+            click_limited: row.max_clicks.is_some(),
+            passthrough: row.passthrough,
+        };
+
+        if target.click_limited {
+            // click limited links are never cached, every visit has to be counted
+            return Ok(Lookup::Found(target));
+        }
+
+        //store the values in cache
+        //TODO probably spawn a background task to do this to save some time on the request
+        // actually benchmark to check if its worth it
+        let deadline = target
+            .expires_at
+            .map(|expires_at| Instant::now() + (expires_at - now).to_std().unwrap_or_default());
+        self.cache_loaded(&*self.cache, key, target.clone(), deadline, generation)
+            .await;
+        Ok(Lookup::Found(target))
+    }
+
+    /// Caches what a load read, unless an invalidation happened since the load saw `generation`.
+    /// One can also land while inserting, then the entry is dropped again right away.
+    async fn cache_loaded<V: Send + 'static>(
+        &self,
+        cache: &dyn CacheBackend<V>,
+        key: &str,
+        value: V,
+        deadline: Option<Instant>,
+        generation: u64,
+    ) {
+        if self.generation.load(Ordering::SeqCst) != generation {
+            tracing::debug!("Not caching {}, it may have changed while loading", key);
+            return;
+        }
+        match deadline {
+            Some(deadline) => cache.insert_until(key.to_string(), value, deadline).await,
+            None => cache.insert(key.to_string(), value).await,
+        }
+        if self.generation.load(Ordering::SeqCst) != generation {
+            cache.remove(key).await;
+        }
     }
-    pub async fn insert(&self, value: String) -> Result<ShortUrlRow, String> {
-        let shorturl = self.generate_short_url();
+
+    /// Store a new short url, using `alias` as the short code if given, otherwise a generated one
+    pub async fn insert(&self, owner_id: i64, mut new: NewShortUrl) -> AppResult<ShortUrlRow> {
+        new.longurl = self.policy.normalize(&new.longurl)?;
+        self.check_blocklist(&new.longurl)?;
+        new.utm = new.utm.normalize()?.without_empty();
+        if let Some(max_clicks) = new.max_clicks
+            && max_clicks < 1
+        {
+            return Err(AppError::ValidationError(
+                "The click limit has to be at least 1".to_string(),
+            ));
+        }
         let timestamp = Utc::now();
+        if new
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= timestamp)
+        {
+            return Err(AppError::ValidationError(
+                "The expiry date has to be in the future".to_string(),
+            ));
+        }
+
+        if let Some(alias) = &new.alias {
+            validate_alias(alias)?;
+            return match self.insert_row(alias, owner_id, &new, timestamp).await {
+                Ok(row) => Ok(row),
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                    AppError::Conflict(format!("The alias \"{alias}\" is already taken")),
+                ),
+                Err(e) => Err(e.into()),
+            };
+        }
+
+        for attempt in 0..MAX_GENERATE_ATTEMPTS {
+            let shorturl = self.generate_short_url(attempt).await?;
+            if is_reserved(&shorturl) {
+                continue;
+            }
+            match self.insert_row(&shorturl, owner_id, &new, timestamp).await {
+                Ok(row) => return Ok(row),
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                    tracing::warn!(attempt, "Generated short url {} is already taken", shorturl);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(AppError::ServiceUnavailable(
+            "Unable to generate an unused short url, please try again".to_string(),
+        ))
+    }
+
+    fn check_blocklist(&self, longurl: &str) -> AppResult<()> {
+        if let Some(pattern) = self.blocklist.blocked_by(longurl) {
+            tracing::warn!("Rejected {} blocked by {}", longurl, pattern);
+            return Err(AppError::ValidationError(
+                "Links to this domain are not allowed".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the raw sqlx error so `insert` can tell unique violations apart
+    async fn insert_row(
+        &self,
+        shorturl: &str,
+        owner_id: i64,
+        new: &NewShortUrl,
+        created_at: DateTime<Utc>,
+    ) -> Result<ShortUrlRow, sqlx::Error> {
         sqlx::query!(
-            "INSERT INTO shorturls (shorturl, longurl , created_at) VALUES (?, ? , ?)",
+            "INSERT INTO shorturls (shorturl, longurl , created_at, owner_id, expires_at, max_clicks, redirect_type, passthrough,
+            utm_source, utm_medium, utm_campaign, utm_term, utm_content)
+            VALUES (?, ? , ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             shorturl,
-            value,
-            timestamp
+            new.longurl,
+            created_at,
+            owner_id,
+            new.expires_at,
+            new.max_clicks,
+            new.redirect_type,
+            new.passthrough,
+            new.utm.utm_source,
+            new.utm.utm_medium,
+            new.utm.utm_campaign,
+            new.utm.utm_term,
+            new.utm.utm_content
         )
         .execute(&self.sqlite_pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .await?;
+        self.invalidate(shorturl).await;
 
         Ok(ShortUrlRow {
-            shorturl,
-            longurl: value,
-            created_at: timestamp,
+            shorturl: shorturl.to_string(),
+            longurl: new.longurl.clone(),
+            created_at,
+            expires_at: new.expires_at,
+            max_clicks: new.max_clicks,
+            click_count: 0,
+            owner_id: Some(owner_id),
+            disabled_at: None,
+            redirect_type: new.redirect_type,
+            passthrough: new.passthrough,
+            utm_source: new.utm.utm_source.clone(),
+            utm_medium: new.utm.utm_medium.clone(),
+            utm_campaign: new.utm.utm_campaign.clone(),
+            utm_term: new.utm.utm_term.clone(),
+            utm_content: new.utm.utm_content.clone(),
         })
     }
 
-    pub async fn get_all(&self) -> Result<Vec<ShortUrlRow>, sqlx::Error> {
-        sqlx::query!("SELECT * FROM shorturls ORDER BY created_at DESC")
-            .map(|row| ShortUrlRow {
-                shorturl: row.shorturl,
-                longurl: row.longurl,
-                created_at: row.created_at.and_utc(),
-            })
-            .fetch_all(&self.sqlite_pool)
-            .await
+    /// A single short url with all its details, unlike `get` this never uses the cache
+    pub async fn get_row(&self, shorturl: &str) -> AppResult<Option<ShortUrlRow>> {
+        let row = sqlx::query_as!(
+            ShortUrlRow,
+            r#"SELECT shorturl, longurl, created_at as "created_at: DateTime<Utc>",
+            expires_at as "expires_at: DateTime<Utc>", max_clicks, click_count, owner_id,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough, utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            FROM shorturls WHERE shorturl = ?"#,
+            shorturl
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// Applies the changes to a short url and returns the updated row
+    pub async fn update(&self, shorturl: &str, changes: LinkChanges) -> AppResult<ShortUrlRow> {
+        let longurl = match changes.longurl {
+            Some(longurl) => {
+                let longurl = self.policy.normalize(&longurl)?;
+                self.check_blocklist(&longurl)?;
+                Some(longurl)
+            }
+            None => None,
+        };
+        let utm = changes.utm.normalize()?;
+        let set_redirect_type = changes.redirect_type.is_some();
+        let redirect_type = changes.redirect_type.flatten();
+        let row = sqlx::query_as!(
+            ShortUrlRow,
+            r#"UPDATE shorturls SET longurl = COALESCE(?, longurl),
+            redirect_type = CASE WHEN ? THEN ? ELSE redirect_type END,
+            passthrough = COALESCE(?, passthrough),
+            utm_source = NULLIF(COALESCE(?, utm_source), ''),
+            utm_medium = NULLIF(COALESCE(?, utm_medium), ''),
+            utm_campaign = NULLIF(COALESCE(?, utm_campaign), ''),
+            utm_term = NULLIF(COALESCE(?, utm_term), ''),
+            utm_content = NULLIF(COALESCE(?, utm_content), '')
+            WHERE shorturl = ?
+            RETURNING shorturl as "shorturl!", longurl as "longurl!", created_at as "created_at!: DateTime<Utc>",
+            expires_at as "expires_at: DateTime<Utc>", max_clicks, click_count as "click_count!", owner_id,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough as "passthrough!: bool", utm_source, utm_medium, utm_campaign, utm_term, utm_content"#,
+            longurl,
+            set_redirect_type,
+            redirect_type,
+            changes.passthrough,
+            utm.utm_source,
+            utm.utm_medium,
+            utm.utm_campaign,
+            utm.utm_term,
+            utm.utm_content,
+            shorturl
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await?;
+        self.invalidate(shorturl).await;
+        row.ok_or_else(url_not_found)
+    }
+
+    /// Stops or resumes redirecting a short url, without deleting it
+    pub async fn set_disabled(&self, shorturl: &str, disabled: bool) -> AppResult<()> {
+        let disabled_at = disabled.then(Utc::now);
+        let result = sqlx::query!(
+            "UPDATE shorturls SET disabled_at = ? WHERE shorturl = ?",
+            disabled_at,
+            shorturl
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+        self.invalidate(shorturl).await;
+        if result.rows_affected() == 0 {
+            return Err(url_not_found());
+        }
+        Ok(())
     }
 
-    fn generate_short_url(&self) -> String {
-        nanoid::nanoid!(8)
+    /// Deletes a short url
+    pub async fn delete(&self, shorturl: &str) -> AppResult<()> {
+        let result = sqlx::query!("DELETE FROM shorturls WHERE shorturl = ?", shorturl)
+            .execute(&self.sqlite_pool)
+            .await?;
+        self.invalidate(shorturl).await;
+        if result.rows_affected() == 0 {
+            return Err(url_not_found());
+        }
+        Ok(())
+    }
+
+    /// Every short url owned by the given user matching the utm `filter`, newest first
+    pub async fn get_all(&self, owner_id: i64, filter: &UtmParams) -> AppResult<Vec<ShortUrlRow>> {
+        let rows = sqlx::query_as!(
+            ShortUrlRow,
+            r#"SELECT shorturl, longurl, created_at as "created_at: DateTime<Utc>",
+            expires_at as "expires_at: DateTime<Utc>", max_clicks, click_count, owner_id,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough, utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            FROM shorturls WHERE owner_id = ?
+            AND utm_source IS COALESCE(?, utm_source) AND utm_medium IS COALESCE(?, utm_medium)
+            AND utm_campaign IS COALESCE(?, utm_campaign) AND utm_term IS COALESCE(?, utm_term)
+            AND utm_content IS COALESCE(?, utm_content)
+            ORDER BY created_at DESC"#,
+            owner_id,
+            filter.utm_source,
+            filter.utm_medium,
+            filter.utm_campaign,
+            filter.utm_term,
+            filter.utm_content
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Every short url in the database regardless of owner, only meant for admins
+    pub async fn get_all_owners(&self) -> AppResult<Vec<ShortUrlRow>> {
+        let rows = sqlx::query_as!(
+            ShortUrlRow,
+            r#"SELECT shorturl, longurl, created_at as "created_at: DateTime<Utc>",
+            expires_at as "expires_at: DateTime<Utc>", max_clicks, click_count, owner_id,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough, utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            FROM shorturls ORDER BY created_at DESC"#
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Every disabled short url, most recently disabled first
+    pub async fn get_disabled(&self) -> AppResult<Vec<ShortUrlRow>> {
+        let rows = sqlx::query_as!(
+            ShortUrlRow,
+            r#"SELECT shorturl, longurl, created_at as "created_at: DateTime<Utc>",
+            expires_at as "expires_at: DateTime<Utc>", max_clicks, click_count, owner_id,
+            disabled_at as "disabled_at: DateTime<Utc>", redirect_type as "redirect_type: RedirectType",
+            passthrough, utm_source, utm_medium, utm_campaign, utm_term, utm_content
+            FROM shorturls WHERE disabled_at IS NOT NULL ORDER BY disabled_at DESC"#
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn generate_short_url(&self, attempt: u32) -> AppResult<String> {
+        let seq = if self.generator.is_sequential() {
+            self.next_sequence().await?
+        } else {
+            0
+        };
+        Ok(self.generator.generate(seq, attempt))
+    }
+
+    /// Hands out the next number of the persistent short code sequence
+    async fn next_sequence(&self) -> AppResult<u64> {
+        let value = sqlx::query_scalar!(
+            "UPDATE short_code_sequence SET value = value + 1 WHERE id = 1 RETURNING value"
+        )
+        .fetch_one(&self.sqlite_pool)
+        .await?;
+        Ok(value as u64)
     }
 }
 
-#[derive(Debug, Clone, sqlx::FromRow)]
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
 pub struct ShortUrlRow {
     pub shorturl: String,
     pub longurl: String,
     pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    pub click_count: i64,
+    /// `None` for urls created before links had owners
+    pub owner_id: Option<i64>,
+    /// Set when an admin disabled the link
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// `None` uses the server default
+    pub redirect_type: Option<RedirectType>,
+    /// Forward the query string and extra path of a visit to the destination
+    pub passthrough: bool,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+}
+
+impl ShortUrlRow {
+    pub fn utm(&self) -> UtmParams {
+        UtmParams {
+            utm_source: self.utm_source.clone(),
+            utm_medium: self.utm_medium.clone(),
+            utm_campaign: self.utm_campaign.clone(),
+            utm_term: self.utm_term.clone(),
+            utm_content: self.utm_content.clone(),
+        }
+    }
+}
+
+/// Everything needed to create a short url, besides its owner
+#[derive(Debug, Clone, Default)]
+pub struct NewShortUrl {
+    pub longurl: String,
+    /// Custom short code, one is generated if `None`
+    pub alias: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_clicks: Option<i64>,
+    /// `None` uses the server default
+    pub redirect_type: Option<RedirectType>,
+    pub passthrough: bool,
+    pub utm: UtmParams,
+}
+
+/// Fields of an existing short url to change, `None` leaves a field as it is
+#[derive(Debug, Clone, Default)]
+pub struct LinkChanges {
+    pub longurl: Option<String>,
+    /// `Some(None)` goes back to the server default
+    pub redirect_type: Option<Option<RedirectType>>,
+    pub passthrough: Option<bool>,
+    /// `None` keeps a parameter, an empty string clears it
+    pub utm: UtmParams,
+}
+
+/// Where a short url leads to
+#[derive(Debug, Clone)]
+pub enum Destination {
+    Url(RedirectTarget),
+    /// The link exists, but is past its expiry date or click limit
+    Expired,
+    /// An admin disabled the link or its destination is on the blocklist
+    Disabled,
+}
+
+/// What a database lookup found, shared by the requests waiting for it
+#[derive(Debug, Clone)]
+enum Lookup {
+    Found(RedirectTarget),
+    Missing,
+    Expired,
+    Disabled,
+}
+
+/// The error for a short url that doesn't exist, or that the user is not allowed to see
+pub fn url_not_found() -> AppError {
+    AppError::NotFound("Url not found".to_string())
+}
+
+/// Shortest and longest alias a user can pick
+const ALIAS_LENGTH: std::ops::RangeInclusive<usize> = 3..=64;
+
+/// Paths that are used by routes of the shortener itself and can't be used as an alias
+const RESERVED_ALIASES: &[&str] = &[
+    "add",
+    "admin",
+    "api",
+    "favicon.ico",
+    "links",
+    "login",
+    "logout",
+    "metrics",
+    "robots.txt",
+    "signup",
+    "static",
+    "stats",
+    "tokens",
+];
+
+fn is_reserved(shorturl: &str) -> bool {
+    RESERVED_ALIASES.contains(&shorturl.to_ascii_lowercase().as_str())
+}
+
+/// Checks that a user supplied alias is a usable short code
+fn validate_alias(alias: &str) -> AppResult<()> {
+    if !ALIAS_LENGTH.contains(&alias.len()) {
+        return Err(AppError::ValidationError(format!(
+            "The alias must be between {} and {} characters long",
+            ALIAS_LENGTH.start(),
+            ALIAS_LENGTH.end()
+        )));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(AppError::ValidationError(
+            "The alias can only contain letters, numbers, '-' and '_'".to_string(),
+        ));
+    }
+    if is_reserved(alias) {
+        return Err(AppError::Conflict(format!(
+            "The alias \"{alias}\" is reserved"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::TtlCache, test_utils, user_store::UserStore};
+    use async_trait::async_trait;
+    use std::{num::NonZeroUsize, time::Duration};
+
+    /// A store on `pool`, like one of several instances sharing a database
+    async fn instance(pool: &Pool<Sqlite>, stats_tx: mpsc::Sender<ClickEvent>) -> UrlStore {
+        let blocklist = Blocklist::load(pool.clone(), None).await.unwrap();
+        test_utils::url_store(pool, stats_tx, blocklist).await
+    }
+
+    /// A store on a fresh in memory database, with an owner for links whose id is returned too
+    async fn store() -> (UrlStore, i64) {
+        let pool = test_utils::pool().await;
+        let owner = UserStore::new(pool.clone(), Duration::from_secs(60))
+            .create_user("owner@example.com", "Owner", "hash", false)
+            .await
+            .unwrap();
+        let (stats_tx, _) = mpsc::channel(1);
+        (instance(&pool, stats_tx).await, owner.id)
+    }
+
+    fn link(alias: &str, url: &str) -> NewShortUrl {
+        NewShortUrl {
+            longurl: url.to_string(),
+            alias: Some(alias.to_string()),
+            ..Default::default()
+        }
+    }
+
+    async fn destination(store: &UrlStore, shorturl: &str) -> Option<String> {
+        match store.get(shorturl.to_string()).await.unwrap() {
+            Some(Destination::Url(target)) => Some(target.url),
+            Some(Destination::Expired) => Some("expired".to_string()),
+            Some(Destination::Disabled) => Some("disabled".to_string()),
+            None => None,
+        }
+    }
+
+    fn target(url: &str) -> RedirectTarget {
+        RedirectTarget {
+            url: url.to_string(),
+            redirect_type: RedirectType::Found,
+            expires_at: None,
+            click_limited: false,
+            passthrough: false,
+        }
+    }
+
+    /// Lets an invalidation land while an entry is being inserted
+    #[derive(Debug)]
+    struct RacingCache {
+        inner: TtlCache<RedirectTarget>,
+        generation: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl CacheBackend<RedirectTarget> for RacingCache {
+        async fn get(&self, key: &str) -> Option<RedirectTarget> {
+            self.inner.get(key).await
+        }
+
+        async fn insert(&self, key: String, value: RedirectTarget) {
+            self.inner.insert(key, value).await;
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn insert_until(&self, key: String, value: RedirectTarget, deadline: Instant) {
+            self.inner.insert_until(key, value, deadline).await;
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn remove(&self, key: &str) {
+            self.inner.remove(key).await;
+        }
+
+        async fn evict_local(&self, key: &str) {
+            self.inner.evict_local(key).await;
+        }
+
+        async fn write_metrics(&self, prefix: &str, out: &mut String) {
+            self.inner.write_metrics(prefix, out).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_are_seen_by_the_next_visit() {
+        let (store, owner) = store().await;
+        store
+            .insert(owner, link("abc", "https://example.com/a"))
+            .await
+            .unwrap();
+        assert_eq!(
+            destination(&store, "abc").await.as_deref(),
+            Some("https://example.com/a")
+        );
+
+        let changes = LinkChanges {
+            longurl: Some("https://example.com/b".to_string()),
+            ..Default::default()
+        };
+        store.update("abc", changes).await.unwrap();
+        assert_eq!(
+            destination(&store, "abc").await.as_deref(),
+            Some("https://example.com/b")
+        );
+
+        store.set_disabled("abc", true).await.unwrap();
+        assert_eq!(
+            destination(&store, "abc").await.as_deref(),
+            Some("disabled")
+        );
+        store.set_disabled("abc", false).await.unwrap();
+        store.delete("abc").await.unwrap();
+        assert_eq!(destination(&store, "abc").await, None);
+    }
+
+    #[tokio::test]
+    async fn unknown_short_urls_are_found_once_created() {
+        let (store, owner) = store().await;
+        assert_eq!(destination(&store, "later").await, None);
+        assert!(store.missing.get("later").await.is_some());
+
+        store
+            .insert(owner, link("later", "https://example.com/"))
+            .await
+            .unwrap();
+        assert_eq!(
+            destination(&store, "later").await.as_deref(),
+            Some("https://example.com/")
+        );
+    }
+
+    #[tokio::test]
+    async fn loads_that_raced_an_invalidation_are_not_cached() {
+        let (store, _) = store().await;
+        // the load read the short url, then it changed before the result was cached
+        let generation = store.generation.load(Ordering::SeqCst);
+        store.invalidate("abc").await;
+        store
+            .cache_loaded(
+                &*store.cache,
+                "abc",
+                target("https://old.example"),
+                None,
+                generation,
+            )
+            .await;
+        store
+            .cache_loaded(&*store.missing, "abc", (), None, generation)
+            .await;
+        assert!(store.cache.get("abc").await.is_none());
+        assert!(store.missing.get("abc").await.is_none());
+
+        // nothing changed during this one
+        let generation = store.generation.load(Ordering::SeqCst);
+        store
+            .cache_loaded(
+                &*store.cache,
+                "abc",
+                target("https://new.example"),
+                None,
+                generation,
+            )
+            .await;
+        assert!(store.cache.get("abc").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn invalidations_landing_during_the_insert_drop_the_entry() {
+        let (store, _) = store().await;
+        let (inner, _) = TtlCache::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+            NonZeroUsize::new(10).unwrap(),
+        )
+        .await;
+        let racing = RacingCache {
+            inner,
+            generation: store.generation.clone(),
+        };
+        let generation = store.generation.load(Ordering::SeqCst);
+        store
+            .cache_loaded(
+                &racing,
+                "abc",
+                target("https://old.example"),
+                None,
+                generation,
+            )
+            .await;
+        assert!(racing.get("abc").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn evictions_from_other_instances_count_as_changes() {
+        let (store, _) = store().await;
+        let generation = store.generation.load(Ordering::SeqCst);
+        store.evictor().evict("abc").await;
+        assert_ne!(store.generation.load(Ordering::SeqCst), generation);
+    }
+
+    #[tokio::test]
+    async fn changes_made_through_other_instances_are_evicted() {
+        let (store, owner) = store().await;
+        let (stats_tx, _) = mpsc::channel(1);
+        let other = instance(&store.sqlite_pool, stats_tx).await;
+        let listener = other.spawn_invalidation_listener();
+        // the listener starts from the changes made before it read the log
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        store
+            .insert(owner, link("abc", "https://example.com/a"))
+            .await
+            .unwrap();
+        assert_eq!(
+            destination(&other, "abc").await.as_deref(),
+            Some("https://example.com/a")
+        );
+
+        let changes = LinkChanges {
+            longurl: Some("https://example.com/b".to_string()),
+            ..Default::default()
+        };
+        store.update("abc", changes).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            destination(&other, "abc").await.as_deref(),
+            Some("https://example.com/b")
+        );
+        listener.abort();
+    }
+
+    #[tokio::test]
+    async fn the_invalidation_listener_does_not_keep_the_click_channel_open() {
+        let pool = test_utils::pool().await;
+        let (stats_tx, mut stats_rx) = mpsc::channel(1);
+        let store = instance(&pool, stats_tx).await;
+        let listener = store.spawn_invalidation_listener();
+
+        drop(store);
+        let closed = tokio::time::timeout(Duration::from_secs(1), stats_rx.recv()).await;
+        assert!(matches!(closed, Ok(None)));
+        listener.abort();
+    }
+
+    fn shorturls(rows: Vec<ShortUrlRow>) -> Vec<String> {
+        let mut shorturls: Vec<_> = rows.into_iter().map(|row| row.shorturl).collect();
+        shorturls.sort();
+        shorturls
+    }
+
+    #[tokio::test]
+    async fn owners_only_list_their_own_links() {
+        let (store, alice) = store().await;
+        let bob = test_utils::user(&store.sqlite_pool, "bob@example.com", false)
+            .await
+            .id;
+        for (owner, alias) in [(alice, "alice1"), (alice, "alice2"), (bob, "bob1")] {
+            store
+                .insert(owner, link(alias, "https://example.com/"))
+                .await
+                .unwrap();
+        }
+
+        let all = UtmParams::default();
+        assert_eq!(
+            shorturls(store.get_all(alice, &all).await.unwrap()),
+            ["alice1", "alice2"]
+        );
+        assert_eq!(shorturls(store.get_all(bob, &all).await.unwrap()), ["bob1"]);
+        let carol = test_utils::user(&store.sqlite_pool, "carol@example.com", false)
+            .await
+            .id;
+        assert!(store.get_all(carol, &all).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn admins_list_the_links_of_every_owner() {
+        let (store, alice) = store().await;
+        let bob = test_utils::user(&store.sqlite_pool, "bob@example.com", false)
+            .await
+            .id;
+        store
+            .insert(alice, link("alice1", "https://example.com/"))
+            .await
+            .unwrap();
+        store
+            .insert(bob, link("bob1", "https://example.com/"))
+            .await
+            .unwrap();
+
+        let rows = store.get_all_owners().await.unwrap();
+        let owners: HashMap<_, _> = rows
+            .iter()
+            .map(|row| (row.shorturl.as_str(), row.owner_id))
+            .collect();
+        assert_eq!(owners["alice1"], Some(alice));
+        assert_eq!(owners["bob1"], Some(bob));
+        assert_eq!(shorturls(rows), ["alice1", "bob1"]);
+    }
+
+    #[test]
+    fn accepts_aliases_made_of_url_safe_characters() {
+        assert!(validate_alias("my-link_2").is_ok());
+        assert!(validate_alias("abc").is_ok());
+        assert!(validate_alias(&"a".repeat(64)).is_ok());
+    }
+
+    #[test]
+    fn rejects_aliases_of_the_wrong_length() {
+        assert!(matches!(
+            validate_alias("ab"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            validate_alias(&"a".repeat(65)),
+            Err(AppError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_aliases_with_other_characters() {
+        for alias in ["with space", "slash/es", "dots.too", "ümlaut"] {
+            assert!(
+                matches!(validate_alias(alias), Err(AppError::ValidationError(_))),
+                "{alias} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_aliases_in_any_case() {
+        assert!(matches!(
+            validate_alias("admin"),
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            validate_alias("Login"),
+            Err(AppError::Conflict(_))
+        ));
+    }
 }
@@ -0,0 +1,399 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Debug)]
+pub struct UserStore {
+    sqlite_pool: Pool<Sqlite>,
+    session_ttl: chrono::Duration,
+}
+
+impl UserStore {
+    pub fn new(sqlite_pool: Pool<Sqlite>, session_ttl: Duration) -> Self {
+        UserStore {
+            sqlite_pool,
+            session_ttl: chrono::Duration::from_std(session_ttl)
+                .expect("session ttl is out of range"),
+        }
+    }
+
+    /// Looks up a user by email, returning the stored password hash alongside it
+    pub async fn get_by_email(&self, email: &str) -> Result<Option<(User, String)>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT id, email, name, is_admin, password_hash FROM users WHERE email = ?",
+            email
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            (
+                User {
+                    id: row.id,
+                    email: row.email,
+                    name: row.name,
+                    is_admin: row.is_admin,
+                },
+                row.password_hash,
+            )
+        }))
+    }
+
+    /// Inserts a new user, `password_hash` should come from `hash_pwd`
+    pub async fn create_user(
+        &self,
+        email: &str,
+        name: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<User, sqlx::Error> {
+        let created_at = Utc::now();
+        let id = sqlx::query_scalar!(
+            "INSERT INTO users (email, name, password_hash, is_admin, created_at) VALUES (?, ?, ?, ?, ?) RETURNING id",
+            email,
+            name,
+            password_hash,
+            is_admin,
+            created_at
+        )
+        .fetch_one(&self.sqlite_pool)
+        .await?;
+
+        Ok(User {
+            id,
+            email: email.to_string(),
+            name: name.to_string(),
+            is_admin,
+        })
+    }
+
+    /// Same as [`UserStore::create_user`], but consumes the invite in the same transaction.
+    ///
+    /// Returns `Ok(None)` if the invite was already used or has expired in the meantime.
+    pub async fn create_user_with_invite(
+        &self,
+        invite_token: &str,
+        email: &str,
+        name: &str,
+        password_hash: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.sqlite_pool.begin().await?;
+
+        let id = sqlx::query_scalar!(
+            "INSERT INTO users (email, name, password_hash, created_at) VALUES (?, ?, ?, ?) RETURNING id",
+            email,
+            name,
+            password_hash,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let redeemed = sqlx::query!(
+            "UPDATE invites SET used_by = ?, used_at = ?
+            WHERE token = ? AND used_at IS NULL AND expires_at > ? AND (email IS NULL OR email = ?)",
+            id,
+            now,
+            invite_token,
+            now,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if redeemed.rows_affected() != 1 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+        tx.commit().await?;
+
+        Ok(Some(User {
+            id,
+            email: email.to_string(),
+            name: name.to_string(),
+            is_admin: false,
+        }))
+    }
+
+    /// Creates an invite that lets one person sign up, optionally only with the given email
+    pub async fn create_invite(
+        &self,
+        created_by: i64,
+        email: Option<&str>,
+        ttl: Duration,
+    ) -> Result<Invite, sqlx::Error> {
+        let token = nanoid::nanoid!(32);
+        let created_at = Utc::now();
+        let expires_at =
+            created_at + chrono::Duration::from_std(ttl).expect("invite ttl is out of range");
+        sqlx::query!(
+            "INSERT INTO invites (token, email, created_by, created_at, expires_at) VALUES (?, ?, ?, ?, ?)",
+            token,
+            email,
+            created_by,
+            created_at,
+            expires_at
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+
+        Ok(Invite {
+            token,
+            email: email.map(ToString::to_string),
+            expires_at,
+        })
+    }
+
+    /// Returns the invite if it exists, has not been used and has not expired
+    pub async fn get_valid_invite(&self, token: &str) -> Result<Option<Invite>, sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            "SELECT token, email, expires_at FROM invites WHERE token = ? AND used_at IS NULL AND expires_at > ?",
+            token,
+            now
+        )
+        .map(|row| Invite {
+            token: row.token,
+            email: row.email,
+            expires_at: row.expires_at.and_utc(),
+        })
+        .fetch_optional(&self.sqlite_pool)
+        .await
+    }
+
+    /// Every invite that can still be used, newest first
+    pub async fn get_pending_invites(&self) -> Result<Vec<Invite>, sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            "SELECT token, email, expires_at FROM invites WHERE used_at IS NULL AND expires_at > ? ORDER BY created_at DESC",
+            now
+        )
+        .map(|row| Invite {
+            token: row.token,
+            email: row.email,
+            expires_at: row.expires_at.and_utc(),
+        })
+        .fetch_all(&self.sqlite_pool)
+        .await
+    }
+
+    /// Creates a new server-side session for the user and returns it
+    pub async fn create_session(&self, user_id: i64) -> Result<Session, sqlx::Error> {
+        let id = nanoid::nanoid!(32);
+        let created_at = Utc::now();
+        let expires_at = created_at + self.session_ttl;
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)",
+            id,
+            user_id,
+            created_at,
+            expires_at
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+
+        Ok(Session { id, expires_at })
+    }
+
+    /// Returns the user owning the session, as long as the session has not expired
+    pub async fn get_session_user(&self, session_id: &str) -> Result<Option<User>, sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            User,
+            "SELECT users.id, users.email, users.name, users.is_admin FROM sessions
+            INNER JOIN users ON users.id = sessions.user_id
+            WHERE sessions.id = ? AND sessions.expires_at > ?",
+            session_id,
+            now
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await
+    }
+
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM sessions WHERE id = ?", session_id)
+            .execute(&self.sqlite_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes every expired session, returns how many were deleted
+    pub async fn delete_expired_sessions(&self) -> Result<u64, sqlx::Error> {
+        let now = Utc::now();
+        let result = sqlx::query!("DELETE FROM sessions WHERE expires_at <= ?", now)
+            .execute(&self.sqlite_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Creates an api token, the returned string is the only time the token is available in plain text
+    pub async fn create_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        scope: TokenScope,
+    ) -> Result<(ApiToken, String), sqlx::Error> {
+        let token = format!("{API_TOKEN_PREFIX}{}", nanoid::nanoid!(40));
+        let token_hash = hash_token(&token);
+        let created_at = Utc::now();
+        let id = sqlx::query_scalar!(
+            "INSERT INTO api_tokens (user_id, name, token_hash, scope, created_at) VALUES (?, ?, ?, ?, ?)
+            RETURNING id",
+            user_id,
+            name,
+            token_hash,
+            scope,
+            created_at
+        )
+        .fetch_one(&self.sqlite_pool)
+        .await?;
+
+        let api_token = ApiToken {
+            id,
+            name: name.to_string(),
+            scope,
+            created_at,
+            last_used_at: None,
+        };
+        Ok((api_token, token))
+    }
+
+    /// The tokens of a user, newest first
+    pub async fn get_api_tokens(&self, user_id: i64) -> Result<Vec<ApiToken>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"SELECT id, name, scope as "scope: TokenScope", created_at as "created_at: DateTime<Utc>",
+            last_used_at as "last_used_at: DateTime<Utc>"
+            FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC"#,
+            user_id
+        )
+        .fetch_all(&self.sqlite_pool)
+        .await
+    }
+
+    /// Revokes a token of the user, returns `false` if the user has no such token
+    pub async fn delete_api_token(&self, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+            id,
+            user_id
+        )
+        .execute(&self.sqlite_pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Looks up the owner of a plain text token and notes that the token was used
+    pub async fn get_token_user(
+        &self,
+        token: &str,
+    ) -> Result<Option<(User, TokenScope)>, sqlx::Error> {
+        let token_hash = hash_token(token);
+        let now = Utc::now();
+        let row = sqlx::query!(
+            r#"UPDATE api_tokens SET last_used_at = ? WHERE token_hash = ?
+            RETURNING user_id, scope as "scope: TokenScope""#,
+            now,
+            token_hash
+        )
+        .fetch_optional(&self.sqlite_pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let user = sqlx::query_as!(
+            User,
+            "SELECT id, email, name, is_admin FROM users WHERE id = ?",
+            row.user_id
+        )
+        .fetch_one(&self.sqlite_pool)
+        .await?;
+        Ok(Some((user, row.scope)))
+    }
+
+    /// Spawn a background task that deletes expired sessions every `interval`
+    pub fn spawn_session_cleaner(&self, interval: Duration) -> JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match store.delete_expired_sessions().await {
+                    Ok(n) => tracing::debug!("Removed {} expired sessions", n),
+                    Err(e) => tracing::error!("Unable to remove expired sessions: {}", e),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: i64,
+    pub email: String,
+    pub name: String,
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Prepended to every api token, so leaked tokens are easy to recognize
+const API_TOKEN_PREFIX: &str = "yaus_";
+
+/// Tokens are long random strings, a plain sha256 is enough to not store them in the clear
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// What an api token is allowed to do, every scope includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Only list and view links
+    Read,
+    /// Also create new links
+    Create,
+    /// Also edit and delete the links of the owner
+    Manage,
+    /// Everything the owner can do, for admins that includes managing the links of everyone
+    Admin,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [Self::Read, Self::Create, Self::Manage, Self::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Create => "create",
+            Self::Manage => "manage",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scope: TokenScope,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Invite {
+    pub token: String,
+    /// When set, the invite can only be used to sign up with this email
+    pub email: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
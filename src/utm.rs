@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use url::{Url, form_urlencoded};
+
+use crate::{
+    errors::{AppError, AppResult},
+    serde_utils::empty_string_as_none,
+};
+
+/// Longest value a single utm parameter can have
+const MAX_UTM_LENGTH: usize = 200;
+
+/// The campaign parameters of a link.
+///
+/// They are stored next to the destination rather than in it, so links can be filtered by
+/// campaign, and are added to the destination on every redirect. Also used as a filter, where
+/// `None` matches any value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtmParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub utm_source: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub utm_medium: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub utm_campaign: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub utm_term: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub utm_content: Option<String>,
+}
+
+impl UtmParams {
+    /// `(query parameter, value)` of every field
+    pub fn fields(&self) -> [(&'static str, Option<&str>); 5] {
+        [
+            ("utm_source", self.utm_source.as_deref()),
+            ("utm_medium", self.utm_medium.as_deref()),
+            ("utm_campaign", self.utm_campaign.as_deref()),
+            ("utm_term", self.utm_term.as_deref()),
+            ("utm_content", self.utm_content.as_deref()),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|(_, value)| value.is_none())
+    }
+
+    /// Trims every value and checks its length.
+    ///
+    /// Empty values are kept, updates use them to clear a field.
+    pub fn normalize(self) -> AppResult<Self> {
+        let normalize = |value: Option<String>| -> AppResult<Option<String>> {
+            let Some(value) = value else {
+                return Ok(None);
+            };
+            let value = value.trim();
+            if value.chars().count() > MAX_UTM_LENGTH {
+                return Err(AppError::ValidationError(format!(
+                    "Utm parameters can be at most {MAX_UTM_LENGTH} characters long"
+                )));
+            }
+            Ok(Some(value.to_string()))
+        };
+        Ok(Self {
+            utm_source: normalize(self.utm_source)?,
+            utm_medium: normalize(self.utm_medium)?,
+            utm_campaign: normalize(self.utm_campaign)?,
+            utm_term: normalize(self.utm_term)?,
+            utm_content: normalize(self.utm_content)?,
+        })
+    }
+
+    /// Drops empty values, for links where there is nothing to clear
+    pub fn without_empty(self) -> Self {
+        let keep = |value: Option<String>| value.filter(|value| !value.is_empty());
+        Self {
+            utm_source: keep(self.utm_source),
+            utm_medium: keep(self.utm_medium),
+            utm_campaign: keep(self.utm_campaign),
+            utm_term: keep(self.utm_term),
+            utm_content: keep(self.utm_content),
+        }
+    }
+
+    /// The parameters as a query string, for links to filtered pages
+    pub fn to_query(&self) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for (name, value) in self.fields() {
+            if let Some(value) = value {
+                query.append_pair(name, value);
+            }
+        }
+        query.finish()
+    }
+
+    /// Adds the parameters to `url`, replacing utm parameters the destination sets itself
+    pub fn apply(&self, url: &str) -> String {
+        if self.is_empty() {
+            return url.to_string();
+        }
+        let Ok(mut parsed) = Url::parse(url) else {
+            return url.to_string();
+        };
+        if parsed.cannot_be_a_base() {
+            return url.to_string();
+        }
+
+        let replaced: HashSet<&str> = self
+            .fields()
+            .into_iter()
+            .filter(|(_, value)| value.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        // the rest of the destination query is copied as is, so its encoding is kept
+        let mut query = parsed
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                form_urlencoded::parse(pair.as_bytes())
+                    .next()
+                    .is_none_or(|(key, _)| !replaced.contains(key.as_ref()))
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&self.to_query());
+        parsed.set_query(Some(&query));
+        parsed.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn campaign(source: &str, campaign: &str) -> UtmParams {
+        UtmParams {
+            utm_source: Some(source.to_string()),
+            utm_campaign: Some(campaign.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn appends_the_parameters_to_the_destination() {
+        assert_eq!(
+            campaign("news letter", "spring").apply("https://example.com/sale"),
+            "https://example.com/sale?utm_source=news+letter&utm_campaign=spring"
+        );
+    }
+
+    #[test]
+    fn replaces_utm_parameters_the_destination_sets_and_keeps_the_rest() {
+        assert_eq!(
+            campaign("news", "spring")
+                .apply("https://example.com/?utm_source=old&q=a%20b&utm_medium=email"),
+            "https://example.com/?q=a%20b&utm_medium=email&utm_source=news&utm_campaign=spring"
+        );
+    }
+
+    #[test]
+    fn leaves_the_destination_alone_without_parameters() {
+        let url = "https://example.com/?utm_source=old";
+        assert_eq!(UtmParams::default().apply(url), url);
+        assert_eq!(
+            campaign("news", "spring").apply("mailto:someone@example.com"),
+            "mailto:someone@example.com"
+        );
+    }
+
+    #[test]
+    fn normalizing_trims_and_limits_the_length() {
+        let params = campaign("  news ", "").normalize().unwrap();
+        assert_eq!(params.utm_source.as_deref(), Some("news"));
+        assert_eq!(params.utm_campaign.as_deref(), Some(""));
+        assert_eq!(params.without_empty().utm_campaign, None);
+
+        let too_long = campaign(&"x".repeat(MAX_UTM_LENGTH + 1), "spring");
+        assert!(matches!(
+            too_long.normalize(),
+            Err(AppError::ValidationError(_))
+        ));
+    }
+}
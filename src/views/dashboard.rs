@@ -1,13 +1,31 @@
-use axum::response::IntoResponse;
+use axum::{http::StatusCode, response::IntoResponse};
 use hypertext::{Raw, prelude::*};
 
 use crate::views::page::Page;
 
+use crate::redirect::RedirectType;
 use crate::url_store::ShortUrlRow as ShortUrlRowModel;
+use crate::user_store::User;
+use crate::utm::UtmParams;
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct DashboardPageBuilder {
     rows: Vec<ShortUrlRowModel>,
+    user: Option<User>,
+    title: String,
+    /// Set when the rows are narrowed down by utm parameters
+    filter: Option<UtmParams>,
+}
+
+impl Default for DashboardPageBuilder {
+    fn default() -> Self {
+        Self {
+            rows: Vec::new(),
+            user: None,
+            title: "Dashboard".to_string(),
+            filter: None,
+        }
+    }
 }
 
 impl DashboardPageBuilder {
@@ -18,6 +36,19 @@ impl DashboardPageBuilder {
         self.rows = rows;
         self
     }
+    pub fn set_user(mut self, user: User) -> Self {
+        self.user = Some(user);
+        self
+    }
+    pub fn set_title(mut self, title: impl ToString) -> Self {
+        self.title = title.to_string();
+        self
+    }
+    /// Shows the utm filter bar, with the current filter filled in
+    pub fn set_filter(mut self, filter: UtmParams) -> Self {
+        self.filter = Some(filter);
+        self
+    }
 }
 
 const UP_ARROW_SVG: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" class="h-6 w-6" fill="none" viewBox="0 0 24 24" stroke="currentColor" stroke-width="2"> <path stroke-linecap="round" stroke-linejoin="round" d="M5 15l7-7 7 7"/></svg>"#;
@@ -25,14 +56,21 @@ const UP_ARROW_SVG: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" class="h-6
 impl Renderable for DashboardPageBuilder {
     fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
         maud! {
-            Page title="Dashboard" {
+            Page title=(&self.title) {
                 div
                     id="scrollTopBtn"
                     class="hidden fixed bottom-6 right-6 bg-blue-600 text-white p-3 rounded-full shadow-lg cursor-pointer hover:bg-blue-700 transition"
                 { (Raw::dangerously_create(UP_ARROW_SVG)) }
 
+                @if let Some(user) = &self.user {
+                    UserNav user=(user);
+                }
+
                 main class="container mx-auto mt-10" {
                     AddUrlForm;
+                    @if let Some(filter) = &self.filter {
+                        UtmFilterForm filter=(filter);
+                    }
                     section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
                         table
                             class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400"
@@ -44,9 +82,11 @@ impl Renderable for DashboardPageBuilder {
                                     th class="px-6 py-3" { "Url" }
                                     th class="px-6 py-3" { "Redirects To" }
                                     th class="px-6 py-3" { "Created At" }
+                                    th class="px-6 py-3" { "Expires" }
+                                    th class="px-6 py-3" { span class="sr-only" { "Actions" } }
                                 }
                             }
-                            tbody #urltablebody hx-on::after-swap="convertUtcTimeToLocal(event.target.children.item(0).children.item(2))" {
+                            tbody #urltablebody hx-on::after-swap="event.target.children.item(0).querySelectorAll('[data-time]').forEach(convertUtcTimeToLocal)" {
                                 @for row in &self.rows {
                                     UrlTableRow data=(row);
                                 }
@@ -65,6 +105,64 @@ impl IntoResponse for DashboardPageBuilder {
     }
 }
 
+#[component]
+pub fn user_nav<'a>(user: &'a User) -> impl Renderable {
+    maud! {
+        nav class="container mx-auto mt-6 flex flex-row items-center justify-end gap-4 text-sm" {
+            a href="/" class="hover:underline" { "My links" }
+            a href="/tokens" class="hover:underline" { "API tokens" }
+            @if user.is_admin {
+                a href="/admin/links" class="hover:underline" { "All links" }
+                a href="/admin/invites" class="hover:underline" { "Invites" }
+                a href="/admin/reports" class="hover:underline" { "Reports" }
+                a href="/admin/blocklist" class="hover:underline" { "Blocklist" }
+            }
+            span class="text-gray-400" title=(user.email) { (user.name) }
+            form method="post" action="/logout" {
+                button
+                    type="submit"
+                    class="rounded-lg border border-gray-600 px-4 py-1 hover:bg-gray-700"
+                { "Logout" }
+            }
+        }
+    }
+}
+
+/// Narrows the dashboard down to a campaign, and links to its combined stats
+#[component]
+fn utm_filter_form<'a>(filter: &'a UtmParams) -> impl Renderable {
+    maud! {
+        form class="mb-4 flex flex-row items-center gap-2 text-sm" method="get" action="/" {
+            @for (name, value) in filter.fields().into_iter().take(3) {
+                input
+                    class="block w-48 p-2 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white"
+                    name=(name)
+                    placeholder=(name)
+                    value=(value.unwrap_or_default());
+            }
+            button
+                type="submit"
+                class="rounded-lg border border-gray-600 px-4 py-2 hover:bg-gray-700"
+            { "Filter" }
+            @if !filter.is_empty() {
+                a href="/" class="hover:underline" { "Clear" }
+            }
+            a href=(format!("/stats?{}", filter.to_query())) class="ms-auto hover:underline" {
+                @if filter.is_empty() { "Stats of all links" } @else { "Stats of these links" }
+            }
+        }
+    }
+}
+
+/// Choices for the expiry select of the add url form, in seconds
+const EXPIRY_OPTIONS: [(&str, &str); 5] = [
+    ("Never expires", ""),
+    ("1 hour", "3600"),
+    ("1 day", "86400"),
+    ("7 days", "604800"),
+    ("30 days", "2592000"),
+];
+
 #[component]
 pub fn add_url_form() -> impl Renderable {
     maud! {
@@ -72,15 +170,16 @@ pub fn add_url_form() -> impl Renderable {
             class="w-full mb-10 mx-auto shadow-md sm:rounded-lg p-2 bg-white border dark:bg-gray-800 dark:border-gray-700 border-gray-200"
         {
             form
-                class="flex flex-row gap-2"
+                class="flex flex-col gap-2"
                 method="post"
                 action="/add"
                 hx-post="/add"
                 hx-target="#urltablebody"
                 hx-swap="afterbegin"
-                hx-on::after-request="this.reset(); this.querySelectorAll('input, button').forEach(el => el.disabled = false);"
+                hx-on::after-request="if (event.detail.successful) { this.reset(); document.getElementById('add-url-error').replaceChildren(); } this.querySelectorAll('input, button').forEach(el => el.disabled = false);"
                 hx-on::before-request="this.querySelectorAll('input, button').forEach(el => el.disabled = true);"
             {
+                div class="flex flex-row gap-2" {
                 label
                     for="add-url"
                     class="mb-2 text-sm font-medium text-gray-900 sr-only dark:text-white"
@@ -91,12 +190,119 @@ pub fn add_url_form() -> impl Renderable {
                     placeholder="Add a new URL"
                     name="url"
                     required;
+                label
+                    for="add-alias"
+                    class="mb-2 text-sm font-medium text-gray-900 sr-only dark:text-white"
+                { "alias" }
+                input
+                    id="add-alias"
+                    class="disabled:opacity-50 disabled:cursor-not-allowed block w-64 p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                    placeholder="Custom alias (optional)"
+                    name="alias"
+                    pattern="[A-Za-z0-9_\\-]{3,64}";
+                label
+                    for="add-expires-in"
+                    class="mb-2 text-sm font-medium text-gray-900 sr-only dark:text-white"
+                { "expires" }
+                select
+                    id="add-expires-in"
+                    class="disabled:opacity-50 disabled:cursor-not-allowed block p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                    name="expires_in"
+                {
+                    @for (label, secs) in EXPIRY_OPTIONS {
+                        option value=(secs) { (label) }
+                    }
+                }
+                label
+                    for="add-max-clicks"
+                    class="mb-2 text-sm font-medium text-gray-900 sr-only dark:text-white"
+                { "max clicks" }
+                input
+                    id="add-max-clicks"
+                    type="number"
+                    min="1"
+                    class="disabled:opacity-50 disabled:cursor-not-allowed block w-36 p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                    placeholder="Max clicks"
+                    name="max_clicks";
+                label
+                    for="add-redirect-type"
+                    class="mb-2 text-sm font-medium text-gray-900 sr-only dark:text-white"
+                { "redirect" }
+                select
+                    id="add-redirect-type"
+                    class="disabled:opacity-50 disabled:cursor-not-allowed block p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                    name="redirect_type"
+                {
+                    option value="" { "Default redirect" }
+                    @for redirect_type in RedirectType::ALL {
+                        option value=(redirect_type.to_string()) { (redirect_type.to_string()) }
+                    }
+                }
+                label
+                    class="flex items-center gap-2 px-2 text-sm text-gray-900 whitespace-nowrap dark:text-white"
+                    title="Forward the query string and extra path of a visit to the destination"
+                {
+                    input
+                        type="checkbox"
+                        name="passthrough"
+                        value="true"
+                        class="w-4 h-4 text-blue-600 bg-gray-100 border-gray-300 rounded-sm focus:ring-blue-500 dark:bg-gray-700 dark:border-gray-600";
+                    "Pass through"
+                }
                 button
                     type="Add url"
                     class="disabled:opacity-50 disabled:cursor-not-allowed text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-8 py-2 dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800 "
                 { "Add" }
+                }
+                details class="px-2 text-sm text-gray-900 dark:text-white" {
+                    summary class="cursor-pointer" { "Campaign (utm parameters)" }
+                    div class="mt-2 flex flex-row gap-2" {
+                        @for (name, _) in UtmParams::default().fields() {
+                            input
+                                class="disabled:opacity-50 disabled:cursor-not-allowed block w-full p-2 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                                name=(name)
+                                placeholder=(name);
+                        }
+                    }
+                }
             }
+            p #add-url-error class="text-sm text-red-500 px-2" {}
+        }
+    }
+}
+
+/// Error message swapped into the add url form when an htmx add fails
+pub struct AddUrlError {
+    status: StatusCode,
+    msg: String,
+}
+
+impl AddUrlError {
+    pub fn new(status: StatusCode, msg: impl ToString) -> Self {
+        Self {
+            status,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl Renderable for AddUrlError {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            span { (self.msg) }
         }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for AddUrlError {
+    fn into_response(self) -> axum::response::Response {
+        // the form targets the table body, point htmx at the error slot instead
+        let headers = [
+            ("HX-Retarget", "#add-url-error"),
+            ("HX-Reswap", "innerHTML"),
+        ];
+        (self.status, headers, self.render()).into_response()
     }
 }
 
@@ -128,11 +334,126 @@ impl<'a> Renderable for UrlTableRow<'a> {
                 th
                     scope="row"
                     class="px-6 py-4 font-medium text-gray-900 whitespace-nowrap dark:text-white data-time"
-                { (row.shorturl) }
+                {
+                    a
+                        href=(format!("/links/{}/stats", row.shorturl))
+                        class="hover:underline"
+                        title="Show stats"
+                    { (row.shorturl) }
+                }
                 td class="px-6 py-4" {
                     a href=(row.longurl) target="_blank" { (row.longurl) }
+                    @if let Some(redirect_type) = row.redirect_type {
+                        span
+                            class="ms-2 px-2 py-0.5 text-xs font-medium rounded bg-gray-100 text-gray-800 dark:bg-gray-700 dark:text-gray-300"
+                            title="Redirect status"
+                        { (redirect_type.to_string()) }
+                    }
+                    @if row.passthrough {
+                        span
+                            class="ms-2 px-2 py-0.5 text-xs font-medium rounded bg-gray-100 text-gray-800 dark:bg-gray-700 dark:text-gray-300"
+                            title="Query string and extra path are forwarded"
+                        { "pass through" }
+                    }
+                    @if let Some(campaign) = &row.utm_campaign {
+                        a
+                            href=(format!("/?{}", UtmParams { utm_campaign: Some(campaign.clone()), ..Default::default() }.to_query()))
+                            class="ms-2 px-2 py-0.5 text-xs font-medium rounded bg-blue-100 text-blue-800 dark:bg-blue-900 dark:text-blue-300 hover:underline"
+                            title=(row.utm().to_query())
+                        { (campaign) }
+                    }
                 }
                 td class="px-6 py-4" data-time  { (row.created_at.to_string()) }
+                td class="px-6 py-4" {
+                    @if row.disabled_at.is_some() {
+                        span class="block font-medium text-red-500" { "Disabled" }
+                    }
+                    @if let Some(expires_at) = row.expires_at {
+                        span data-time { (expires_at.to_rfc3339()) }
+                    }
+                    @if let Some(max_clicks) = row.max_clicks {
+                        span class="block" { (format!("{}/{} clicks", row.click_count, max_clicks)) }
+                    }
+                    @if row.expires_at.is_none() && row.max_clicks.is_none() && row.disabled_at.is_none() {
+                        "Never"
+                    }
+                }
+                td class="px-6 py-4 whitespace-nowrap text-right" {
+                    button
+                        class="font-medium text-blue-600 dark:text-blue-500 hover:underline me-3"
+                        hx-get=(format!("/links/{}/edit", row.shorturl))
+                        hx-target="closest tr"
+                        hx-swap="outerHTML"
+                    { "Edit" }
+                    a
+                        class="font-medium text-blue-600 dark:text-blue-500 hover:underline me-3"
+                        href=(format!("/links/{}/qr?download=true", row.shorturl))
+                        title="Download a qr code"
+                        download
+                    { "QR" }
+                    button
+                        class="font-medium text-red-600 dark:text-red-500 hover:underline"
+                        hx-delete=(format!("/links/{}", row.shorturl))
+                        hx-target="closest tr"
+                        hx-swap="outerHTML"
+                        hx-confirm=(format!("Delete {}? The short url will stop working.", row.shorturl))
+                    { "Delete" }
+                }
+            }
+        }.render_to(buffer);
+    }
+}
+
+/// A table row with the destination turned into an input, swapped in by the edit button
+pub struct UrlTableRowEdit<'a> {
+    data: &'a ShortUrlRowModel,
+}
+
+impl<'a> UrlTableRowEdit<'a> {
+    pub fn new(row: &'a ShortUrlRowModel) -> Self {
+        Self { data: row }
+    }
+}
+
+impl<'a> IntoResponse for UrlTableRowEdit<'a> {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
+
+impl<'a> Renderable for UrlTableRowEdit<'a> {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        let row = &self.data;
+        maud! {
+            tr
+                class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200"
+            {
+                th
+                    scope="row"
+                    class="px-6 py-4 font-medium text-gray-900 whitespace-nowrap dark:text-white"
+                { (row.shorturl) }
+                td class="px-6 py-4" colspan="3" {
+                    input
+                        class="block w-full p-2 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 focus:ring-blue-500 focus:border-blue-500 dark:bg-gray-700 dark:border-gray-600 dark:text-white"
+                        name="url"
+                        value=(row.longurl)
+                        required;
+                }
+                td class="px-6 py-4 whitespace-nowrap text-right" {
+                    button
+                        class="font-medium text-blue-600 dark:text-blue-500 hover:underline me-3"
+                        hx-put=(format!("/links/{}", row.shorturl))
+                        hx-include="closest tr"
+                        hx-target="closest tr"
+                        hx-swap="outerHTML"
+                    { "Save" }
+                    button
+                        class="font-medium text-gray-600 dark:text-gray-400 hover:underline"
+                        hx-get=(format!("/links/{}", row.shorturl))
+                        hx-target="closest tr"
+                        hx-swap="outerHTML"
+                    { "Cancel" }
+                }
             }
         }.render_to(buffer);
     }
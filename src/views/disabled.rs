@@ -0,0 +1,45 @@
+use axum::{http::StatusCode, response::IntoResponse};
+use hypertext::prelude::*;
+
+use crate::views::page::Page;
+
+/// Shown instead of redirecting when an admin disabled a link or its destination is blocked
+#[derive(Debug, Default)]
+pub struct LinkDisabledPage;
+
+impl LinkDisabledPage {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Renderable for LinkDisabledPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Link disabled" {
+                main
+                    class="grid min-h-full place-items-center bg-gray-900 px-6 py-24 sm:py-32 lg:px-8"
+                {
+                    div class="text-center" {
+                        p class="text-base font-semibold text-indigo-400" {
+                            (StatusCode::GONE.to_string())
+                        }
+                        h1
+                            class="mt-4 text-5xl font-semibold tracking-tight text-balance text-white sm:text-7xl"
+                        { "Link disabled" }
+                        p class="mt-6 text-lg font-medium text-pretty text-gray-400 sm:text-xl/8" {
+                            "This link has been disabled by the administrators."
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for LinkDisabledPage {
+    fn into_response(self) -> axum::response::Response {
+        (StatusCode::GONE, self.render()).into_response()
+    }
+}
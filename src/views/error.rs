@@ -70,3 +70,43 @@ impl IntoResponse for ErrorPage {
         (status, html).into_response()
     }
 }
+
+/// Id of the element on every page that htmx error fragments are swapped into
+pub const ERROR_SLOT_ID: &str = "htmx-error";
+pub const ERROR_SLOT_SELECTOR: &str = "#htmx-error";
+
+/// A dismissable error message, rendered instead of [`ErrorPage`] for htmx requests
+pub struct ErrorFragment<'a> {
+    msg: &'a str,
+}
+
+impl<'a> ErrorFragment<'a> {
+    pub fn new(msg: &'a str) -> Self {
+        Self { msg }
+    }
+}
+
+impl Renderable for ErrorFragment<'_> {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            div
+                class="flex items-center gap-4 p-4 text-sm text-red-800 rounded-lg bg-red-50 shadow-md dark:bg-gray-800 dark:text-red-400"
+                role="alert"
+            {
+                span { (self.msg) }
+                button
+                    type="button"
+                    class="font-medium hover:underline"
+                    onclick="this.parentElement.remove()"
+                { "Dismiss" }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for ErrorFragment<'_> {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
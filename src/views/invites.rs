@@ -0,0 +1,75 @@
+use axum::response::IntoResponse;
+use hypertext::prelude::*;
+
+use crate::views::{dashboard::UserNav, page::Page};
+use crate::user_store::{Invite, User};
+
+#[derive(Debug)]
+pub struct InvitesPage {
+    user: User,
+    invites: Vec<Invite>,
+}
+
+impl InvitesPage {
+    pub fn new(user: User, invites: Vec<Invite>) -> Self {
+        Self { user, invites }
+    }
+}
+
+impl Renderable for InvitesPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Invites" {
+                UserNav user=(&self.user);
+                main class="container mx-auto mt-10" {
+                    section
+                        class="w-full mb-10 mx-auto shadow-md sm:rounded-lg p-2 bg-white border dark:bg-gray-800 dark:border-gray-700 border-gray-200"
+                    {
+                        form class="flex flex-row gap-2" method="post" action="/admin/invites" {
+                            input
+                                type="email"
+                                name="email"
+                                class="block w-full p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white"
+                                placeholder="Only allow this email (optional)";
+                            button
+                                type="submit"
+                                class="text-white bg-blue-700 hover:bg-blue-800 font-medium rounded-lg text-sm px-8 py-2 dark:bg-blue-600 dark:hover:bg-blue-700 whitespace-nowrap"
+                            { "Create invite" }
+                        }
+                    }
+                    section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
+                        table class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400" {
+                            thead class="text-xs text-gray-700 uppercase bg-gray-50 dark:bg-gray-700 dark:text-gray-400" {
+                                tr {
+                                    th class="px-6 py-3" { "Invite link" }
+                                    th class="px-6 py-3" { "Email" }
+                                    th class="px-6 py-3" { "Expires At" }
+                                }
+                            }
+                            tbody {
+                                @for invite in &self.invites {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200" {
+                                        td class="px-6 py-4" {
+                                            a href=(format!("/signup?invite={}", invite.token)) {
+                                                (format!("/signup?invite={}", invite.token))
+                                            }
+                                        }
+                                        td class="px-6 py-4" { (invite.email.as_deref().unwrap_or("anyone")) }
+                                        td class="px-6 py-4" data-time { (invite.expires_at.to_string()) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for InvitesPage {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
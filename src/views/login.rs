@@ -1,5 +1,5 @@
 use axum::{
-    http::{StatusCode, Uri},
+    http::StatusCode,
     response::IntoResponse,
 };
 use hypertext::prelude::*;
@@ -10,6 +10,7 @@ use crate::views::page::Page;
 pub struct LoginFormPage {
     prepopulated_email: Option<String>,
     show_invalid_credentials: bool,
+    show_signup_link: bool,
     redirect_to: Option<String>,
 }
 
@@ -35,6 +36,11 @@ impl LoginFormPage {
         self
     }
 
+    pub fn show_signup_link(mut self, show: bool) -> Self {
+        self.show_signup_link = show;
+        self
+    }
+
     // pub fn set_redirect_to(mut self, redirect_to: impl ToString) -> Self {
     //     self.redirect_to = Some(redirect_to.to_string());
     //     self
@@ -66,6 +72,9 @@ impl Renderable for LoginFormPage {
                         }
                         button type="submit" { "Login" }
                     }
+                    @if self.show_signup_link {
+                        a href="/signup" { "No account yet? Sign up" }
+                    }
                 }
             }
         }
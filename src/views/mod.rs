@@ -1,8 +1,28 @@
 mod dashboard;
+mod disabled;
 mod error;
+mod expired;
+mod invites;
 mod login;
+mod moderation;
 mod page;
-pub use crate::views::{dashboard::*, error::ErrorPage, login::*};
+mod report;
+mod signup;
+mod stats;
+mod tokens;
+pub use crate::views::{
+    dashboard::*,
+    disabled::LinkDisabledPage,
+    error::{ERROR_SLOT_ID, ERROR_SLOT_SELECTOR, ErrorFragment, ErrorPage},
+    expired::LinkExpiredPage,
+    invites::InvitesPage,
+    login::*,
+    moderation::{BlocklistPage, ReportsPage},
+    report::ReportLinkPage,
+    signup::*,
+    stats::LinkStatsPage,
+    tokens::TokensPage,
+};
 
 //pub fn home_page() {}
 
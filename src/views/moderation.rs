@@ -0,0 +1,214 @@
+use axum::response::IntoResponse;
+use hypertext::prelude::*;
+
+use crate::{
+    moderation::{BlocklistEntry, ReportedLink},
+    url_store::ShortUrlRow,
+    user_store::User,
+    views::{dashboard::UserNav, page::Page},
+};
+
+/// The moderation queue, plus every disabled link so they can be enabled again
+#[derive(Debug)]
+pub struct ReportsPage {
+    user: User,
+    reports: Vec<ReportedLink>,
+    disabled: Vec<ShortUrlRow>,
+}
+
+impl ReportsPage {
+    pub fn new(user: User, reports: Vec<ReportedLink>, disabled: Vec<ShortUrlRow>) -> Self {
+        Self {
+            user,
+            reports,
+            disabled,
+        }
+    }
+}
+
+impl Renderable for ReportsPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Reports" {
+                UserNav user=(&self.user);
+                main class="container mx-auto mt-10 flex flex-col gap-10" {
+                    section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
+                        table class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400" {
+                            caption class="p-5 text-lg font-semibold text-left text-gray-900 bg-white dark:text-white dark:bg-gray-800" {
+                                "Reported links"
+                            }
+                            thead class="text-xs text-gray-700 uppercase bg-gray-50 dark:bg-gray-700 dark:text-gray-400" {
+                                tr {
+                                    th class="px-6 py-3" { "Url" }
+                                    th class="px-6 py-3" { "Redirects To" }
+                                    th class="px-6 py-3" { "Reports" }
+                                    th class="px-6 py-3" { "First Reported" }
+                                    th class="px-6 py-3" { span class="sr-only" { "Actions" } }
+                                }
+                            }
+                            tbody {
+                                @for link in &self.reports {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200 align-top" {
+                                        td class="px-6 py-4 font-medium text-gray-900 dark:text-white" { (link.shorturl) }
+                                        // not a link, admins shouldn't accidentally open phishing pages
+                                        td class="px-6 py-4 break-all" { (link.longurl) }
+                                        td class="px-6 py-4" {
+                                            ul class="list-disc" {
+                                                @for reason in &link.reasons {
+                                                    li { (reason) }
+                                                }
+                                            }
+                                        }
+                                        td class="px-6 py-4" data-time { (link.first_reported_at.to_string()) }
+                                        td class="px-6 py-4 whitespace-nowrap text-right" {
+                                            @if !link.disabled {
+                                                form class="inline" method="post" action=(format!("/admin/links/{}/disable", link.shorturl)) {
+                                                    button
+                                                        type="submit"
+                                                        class="font-medium text-red-600 dark:text-red-500 hover:underline me-3"
+                                                    { "Disable" }
+                                                }
+                                            }
+                                            form class="inline" method="post" action=(format!("/admin/reports/{}/dismiss", link.shorturl)) {
+                                                button
+                                                    type="submit"
+                                                    class="font-medium text-blue-600 dark:text-blue-500 hover:underline"
+                                                { "Dismiss" }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
+                        table class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400" {
+                            caption class="p-5 text-lg font-semibold text-left text-gray-900 bg-white dark:text-white dark:bg-gray-800" {
+                                "Disabled links"
+                            }
+                            thead class="text-xs text-gray-700 uppercase bg-gray-50 dark:bg-gray-700 dark:text-gray-400" {
+                                tr {
+                                    th class="px-6 py-3" { "Url" }
+                                    th class="px-6 py-3" { "Redirects To" }
+                                    th class="px-6 py-3" { "Disabled At" }
+                                    th class="px-6 py-3" { span class="sr-only" { "Actions" } }
+                                }
+                            }
+                            tbody {
+                                @for row in &self.disabled {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200" {
+                                        td class="px-6 py-4 font-medium text-gray-900 dark:text-white" { (row.shorturl) }
+                                        td class="px-6 py-4 break-all" { (row.longurl) }
+                                        td class="px-6 py-4" data-time {
+                                            (row.disabled_at.map(|d| d.to_string()).unwrap_or_default())
+                                        }
+                                        td class="px-6 py-4 text-right" {
+                                            form method="post" action=(format!("/admin/links/{}/enable", row.shorturl)) {
+                                                button
+                                                    type="submit"
+                                                    class="font-medium text-blue-600 dark:text-blue-500 hover:underline"
+                                                { "Enable" }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for ReportsPage {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
+
+#[derive(Debug)]
+pub struct BlocklistPage {
+    user: User,
+    file_patterns: Vec<String>,
+    entries: Vec<BlocklistEntry>,
+}
+
+impl BlocklistPage {
+    pub fn new(user: User, file_patterns: Vec<String>, entries: Vec<BlocklistEntry>) -> Self {
+        Self {
+            user,
+            file_patterns,
+            entries,
+        }
+    }
+}
+
+impl Renderable for BlocklistPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Blocklist" {
+                UserNav user=(&self.user);
+                main class="container mx-auto mt-10" {
+                    section
+                        class="w-full mb-10 mx-auto shadow-md sm:rounded-lg p-2 bg-white border dark:bg-gray-800 dark:border-gray-700 border-gray-200"
+                    {
+                        form class="flex flex-row gap-2" method="post" action="/admin/blocklist" {
+                            input
+                                type="text"
+                                name="pattern"
+                                required
+                                class="block w-full p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white"
+                                placeholder="example.com blocks it and its subdomains, * matches anything";
+                            button
+                                type="submit"
+                                class="text-white bg-blue-700 hover:bg-blue-800 font-medium rounded-lg text-sm px-8 py-2 dark:bg-blue-600 dark:hover:bg-blue-700 whitespace-nowrap"
+                            { "Block" }
+                        }
+                    }
+                    section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
+                        table class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400" {
+                            thead class="text-xs text-gray-700 uppercase bg-gray-50 dark:bg-gray-700 dark:text-gray-400" {
+                                tr {
+                                    th class="px-6 py-3" { "Pattern" }
+                                    th class="px-6 py-3" { "Added At" }
+                                    th class="px-6 py-3" { span class="sr-only" { "Actions" } }
+                                }
+                            }
+                            tbody {
+                                @for entry in &self.entries {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200" {
+                                        td class="px-6 py-4 font-medium text-gray-900 dark:text-white" { (entry.pattern) }
+                                        td class="px-6 py-4" data-time { (entry.created_at.to_string()) }
+                                        td class="px-6 py-4 text-right" {
+                                            form method="post" action=(format!("/admin/blocklist/{}/delete", entry.id)) {
+                                                button
+                                                    type="submit"
+                                                    class="font-medium text-red-600 dark:text-red-500 hover:underline"
+                                                { "Remove" }
+                                            }
+                                        }
+                                    }
+                                }
+                                @for pattern in &self.file_patterns {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200" {
+                                        td class="px-6 py-4 font-medium text-gray-900 dark:text-white" { (pattern) }
+                                        td class="px-6 py-4" colspan="2" { "From the blocklist file" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for BlocklistPage {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
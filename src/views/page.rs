@@ -1,5 +1,9 @@
 use hypertext::prelude::*;
 
+use crate::views::ERROR_SLOT_ID;
+
+const HTMX_CONFIG: &str = r#"{"responseHandling":[{"code":"204","swap":false},{"code":"[23]..","swap":true},{"code":"[45]..","swap":true,"error":true},{"code":"...","swap":false}]}"#;
+
 /// Generates the HTML structure for a page with a title and content
 #[component]
 pub fn page<'a, R: Renderable>(title: &'a str, children: &R) -> impl Renderable {
@@ -12,12 +16,17 @@ pub fn page<'a, R: Renderable>(title: &'a str, children: &R) -> impl Renderable
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
                 // TODO for now unpkg is used, but this should be replaced with a local copy in the future
                 script src="https://unpkg.com/htmx.org" {}
+                // swap error responses too, they come as fragments aimed at the error slot or next to a form
+                meta name="htmx-config" content=(HTMX_CONFIG);
                 script src="/static/utils.js" {}
                 // TODO probably figure out a way clean way to use the tailwnindcss cli to also decrease the bundle size
                 //script src="https://cdn.jsdelivr.net/npm/@tailwindcss/browser@4" {}
                 link rel="stylesheet" href="/static/styles.css";
             }
-            body class="bg-gray-900 text-gray-100" { (children) }
+            body class="bg-gray-900 text-gray-100" {
+                div id=(ERROR_SLOT_ID) class="fixed bottom-4 right-4 z-50" aria-live="polite" {}
+                (children)
+            }
         }
     }
 }
@@ -0,0 +1,89 @@
+use axum::{http::StatusCode, response::IntoResponse};
+use hypertext::prelude::*;
+
+use crate::{moderation::MAX_REPORT_REASON_LENGTH, views::page::Page};
+
+/// Lets anyone flag a short url for the admins to review
+#[derive(Debug)]
+pub struct ReportLinkPage {
+    shorturl: String,
+    submitted: bool,
+    error: Option<String>,
+}
+
+impl ReportLinkPage {
+    pub fn new(shorturl: String) -> Self {
+        Self {
+            shorturl,
+            submitted: false,
+            error: None,
+        }
+    }
+
+    /// Thank the visitor instead of showing the form
+    pub fn set_submitted(mut self) -> Self {
+        self.submitted = true;
+        self
+    }
+
+    pub fn set_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+impl Renderable for ReportLinkPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Report a link" {
+                main
+                    class="grid min-h-full place-items-center bg-gray-900 px-6 py-24 sm:py-32 lg:px-8"
+                {
+                    div class="w-full max-w-xl" {
+                        h1 class="text-3xl font-semibold tracking-tight text-white" {
+                            "Report /" (self.shorturl)
+                        }
+                        @if self.submitted {
+                            p class="mt-6 text-lg text-gray-400" {
+                                "Thanks, an admin will look into this link."
+                            }
+                        } @else {
+                            p class="mt-6 text-gray-400" {
+                                "Does this link lead to phishing, malware or other abusive content? Tell us what's wrong with it."
+                            }
+                            form class="mt-6 flex flex-col gap-4" method="post" action=(format!("/{}/report", self.shorturl)) {
+                                textarea
+                                    name="reason"
+                                    rows="4"
+                                    required
+                                    maxlength=(MAX_REPORT_REASON_LENGTH)
+                                    class="block w-full p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white"
+                                    placeholder="What is wrong with this link?"
+                                {}
+                                @if let Some(error) = &self.error {
+                                    p class="text-red-500" { (error) }
+                                }
+                                button
+                                    type="submit"
+                                    class="self-end text-white bg-red-700 hover:bg-red-800 font-medium rounded-lg text-sm px-8 py-2"
+                                { "Report link" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for ReportLinkPage {
+    fn into_response(self) -> axum::response::Response {
+        let status = if self.error.is_some() {
+            StatusCode::UNPROCESSABLE_ENTITY
+        } else {
+            StatusCode::OK
+        };
+        (status, self.render()).into_response()
+    }
+}
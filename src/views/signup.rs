@@ -0,0 +1,101 @@
+use axum::{http::StatusCode, response::IntoResponse};
+use hypertext::prelude::*;
+
+use crate::views::page::Page;
+
+#[derive(Debug, Default)]
+pub struct SignupFormPage {
+    prepopulated_email: Option<String>,
+    prepopulated_name: Option<String>,
+    invite: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SignupFormPayload {
+    pub email: String,
+    pub name: String,
+    pub password: String,
+    pub invite: Option<String>,
+}
+
+impl SignupFormPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn maybe_prepopulated_email(mut self, email: Option<String>) -> Self {
+        self.prepopulated_email = email;
+        self
+    }
+
+    pub fn set_prepopulated_name(mut self, name: impl ToString) -> Self {
+        self.prepopulated_name = Some(name.to_string());
+        self
+    }
+
+    pub fn maybe_invite(mut self, invite: Option<String>) -> Self {
+        self.invite = invite;
+        self
+    }
+
+    pub fn set_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+impl Renderable for SignupFormPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="Sign up" {
+                main {
+                    form action="/signup" method="POST" {
+                        input
+                            type="email"
+                            name="email"
+                            placeholder="email"
+                            required
+                            value=[&self.prepopulated_email];
+                        input
+                            type="text"
+                            name="name"
+                            placeholder="name"
+                            required
+                            value=[&self.prepopulated_name];
+                        input
+                            type="password"
+                            name="password"
+                            placeholder="***"
+                            minlength=(MIN_PASSWORD_LENGTH)
+                            required;
+                        @if let Some(invite) = &self.invite {
+                            input type="hidden" name="invite" value=(invite);
+                        }
+                        @if let Some(error) = &self.error {
+                            p class="text-red-500" { (error) }
+                        }
+                        button type="submit" { "Sign up" }
+                    }
+                    a href="/login" { "Already have an account? Login" }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for SignupFormPage {
+    fn into_response(self) -> axum::response::Response {
+        let html = self.render();
+        let status = if self.error.is_some() {
+            StatusCode::BAD_REQUEST
+        } else {
+            StatusCode::OK
+        };
+        (status, html).into_response()
+    }
+}
+
+/// Shortest password accepted when signing up
+pub const MIN_PASSWORD_LENGTH: usize = 8;
@@ -0,0 +1,197 @@
+use axum::response::IntoResponse;
+use hypertext::prelude::*;
+
+use crate::analytics::LinkStats;
+use crate::url_store::ShortUrlRow as ShortUrlRowModel;
+use crate::user_store::User;
+use crate::utm::UtmParams;
+use crate::views::{dashboard::UserNav, page::Page};
+
+pub struct LinkStatsPage {
+    user: User,
+    heading: String,
+    /// Short url and destination of a single link, stats of several links have neither
+    shorturl: Option<String>,
+    longurl: Option<String>,
+    /// Describes what was added up for combined stats
+    details: Option<String>,
+    stats: LinkStats,
+}
+
+impl LinkStatsPage {
+    pub fn new(user: User, row: ShortUrlRowModel, stats: LinkStats) -> Self {
+        Self {
+            user,
+            heading: row.shorturl.clone(),
+            shorturl: Some(row.shorturl),
+            longurl: Some(row.longurl),
+            details: None,
+            stats,
+        }
+    }
+
+    /// Stats of every link matching the utm `filter`
+    pub fn for_campaign(
+        user: User,
+        filter: &UtmParams,
+        link_count: usize,
+        stats: LinkStats,
+    ) -> Self {
+        let heading = filter
+            .fields()
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{name}={value}")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Self {
+            user,
+            heading: if heading.is_empty() {
+                "All links".to_string()
+            } else {
+                heading
+            },
+            shorturl: None,
+            longurl: None,
+            details: Some(format!("{link_count} links")),
+            stats,
+        }
+    }
+}
+
+impl Renderable for LinkStatsPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        let stats = &self.stats;
+        maud! {
+            Page title=(&format!("Stats for {}", self.heading)) {
+                UserNav user=(&self.user);
+                main class="container mx-auto mt-10 flex flex-col gap-6" {
+                    section class="p-4 shadow-md sm:rounded-lg bg-gray-800 border border-gray-700" {
+                        h1 class="text-2xl font-semibold text-white" { (self.heading) }
+                        @if let Some(longurl) = &self.longurl {
+                            a href=(longurl) target="_blank" class="text-sm text-gray-400 hover:underline" {
+                                (longurl)
+                            }
+                        }
+                        @if let Some(details) = &self.details {
+                            p class="text-sm text-gray-400" { (details) }
+                        }
+                        dl class="mt-4 flex flex-row gap-10" {
+                            div {
+                                dt class="text-sm text-gray-400" { "Total clicks" }
+                                dd class="text-3xl font-semibold text-white" { (stats.total_clicks) }
+                            }
+                            div {
+                                dt class="text-sm text-gray-400" { "Unique visitors" }
+                                dd class="text-3xl font-semibold text-white" { (stats.unique_visitors) }
+                            }
+                        }
+                    }
+                    @if let Some(shorturl) = &self.shorturl {
+                        QrSection shorturl=(shorturl);
+                    }
+                    ClickChart title="Clicks per day (UTC)" buckets=(&stats.daily);
+                    ClickChart title="Clicks per hour (UTC)" buckets=(&stats.hourly);
+                    div class="grid grid-cols-1 md:grid-cols-3 gap-6" {
+                        BreakdownTable title="Top referrers" entries=(&stats.top_referrers) total=(stats.total_clicks);
+                        BreakdownTable title="Browsers" entries=(&stats.browsers) total=(stats.total_clicks);
+                        BreakdownTable title="Operating systems" entries=(&stats.operating_systems) total=(stats.total_clicks);
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for LinkStatsPage {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}
+
+/// Preview of the qr code of a link, with downloads for print
+#[component]
+fn qr_section<'a>(shorturl: &'a String) -> impl Renderable {
+    let qr_url = format!("/links/{shorturl}/qr");
+    maud! {
+        section class="p-4 shadow-md sm:rounded-lg bg-gray-800 border border-gray-700 flex flex-row items-center gap-6" {
+            img src=(format!("{qr_url}?size=160")) width="160" height="160" alt=(format!("Qr code of {shorturl}")) class="rounded";
+            div class="flex flex-col gap-2 text-sm" {
+                h2 class="text-lg font-semibold text-white" { "Qr code" }
+                a href=(format!("{qr_url}?download=true&size=1024")) download class="text-blue-500 hover:underline" {
+                    "Download PNG"
+                }
+                a href=(format!("{qr_url}?download=true&format=svg")) download class="text-blue-500 hover:underline" {
+                    "Download SVG"
+                }
+                p class="text-gray-400" {
+                    "Size, colours and error correction can be changed with "
+                    code { "size" } ", " code { "fg" } ", " code { "bg" } " and " code { "ec" } " in the url."
+                }
+            }
+        }
+    }
+}
+
+/// Bar chart of `(label, clicks)` buckets, drawn with plain divs so no chart library is needed
+#[component]
+fn click_chart<'a>(title: &'a str, buckets: &'a Vec<(String, i64)>) -> impl Renderable {
+    let max = buckets
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    maud! {
+        section class="p-4 shadow-md sm:rounded-lg bg-gray-800 border border-gray-700" {
+            h2 class="mb-4 text-lg font-semibold text-white" { (title) }
+            div class="flex flex-row items-end gap-px h-40" {
+                @for (label, count) in buckets {
+                    div
+                        class="flex-1 bg-blue-600 hover:bg-blue-500 min-h-px"
+                        style=(format!("height: {}%", count * 100 / max))
+                        title=(format!("{label}: {count} clicks"))
+                    {}
+                }
+            }
+            @if let (Some((first, _)), Some((last, _))) = (buckets.first(), buckets.last()) {
+                div class="mt-1 flex flex-row justify-between text-xs text-gray-400" {
+                    span { (first) }
+                    span { (last) }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn breakdown_table<'a>(
+    title: &'a str,
+    entries: &'a Vec<(String, i64)>,
+    total: i64,
+) -> impl Renderable {
+    maud! {
+        section class="p-4 shadow-md sm:rounded-lg bg-gray-800 border border-gray-700" {
+            h2 class="mb-4 text-lg font-semibold text-white" { (title) }
+            @if entries.is_empty() {
+                p class="text-sm text-gray-400" { "No clicks yet" }
+            }
+            ul class="flex flex-col gap-2 text-sm" {
+                @for (name, count) in entries {
+                    li {
+                        div class="flex flex-row justify-between gap-2" {
+                            span class="truncate" title=(name) { (name) }
+                            span class="text-gray-400" { (count) }
+                        }
+                        div class="h-1 bg-gray-700 rounded" {
+                            div
+                                class="h-1 bg-blue-600 rounded"
+                                style=(format!("width: {}%", count * 100 / total.max(1)))
+                            {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
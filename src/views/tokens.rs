@@ -0,0 +1,116 @@
+use axum::response::IntoResponse;
+use hypertext::prelude::*;
+
+use crate::user_store::{ApiToken, TokenScope, User};
+use crate::views::{dashboard::UserNav, page::Page};
+
+#[derive(Debug)]
+pub struct TokensPage {
+    user: User,
+    tokens: Vec<ApiToken>,
+    /// A token that was just created, shown once since only its hash is stored
+    created: Option<String>,
+}
+
+impl TokensPage {
+    pub fn new(user: User, tokens: Vec<ApiToken>) -> Self {
+        Self {
+            user,
+            tokens,
+            created: None,
+        }
+    }
+
+    pub fn set_created(mut self, token: String) -> Self {
+        self.created = Some(token);
+        self
+    }
+}
+
+impl Renderable for TokensPage {
+    fn render_to(&self, buffer: &mut hypertext::Buffer<hypertext::context::Node>) {
+        maud! {
+            Page title="API tokens" {
+                UserNav user=(&self.user);
+                main class="container mx-auto mt-10" {
+                    @if let Some(token) = &self.created {
+                        section
+                            class="w-full mb-6 p-4 text-sm text-green-800 rounded-lg bg-green-50 dark:bg-gray-800 dark:text-green-400"
+                            role="status"
+                        {
+                            p { "Copy your new token now, it won't be shown again:" }
+                            code class="block mt-2 font-mono break-all select-all" { (token) }
+                        }
+                    }
+                    section
+                        class="w-full mb-10 mx-auto shadow-md sm:rounded-lg p-2 bg-white border dark:bg-gray-800 dark:border-gray-700 border-gray-200"
+                    {
+                        form class="flex flex-row gap-2" method="post" action="/tokens" {
+                            input
+                                type="text"
+                                name="name"
+                                required
+                                maxlength="100"
+                                class="block w-full p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white"
+                                placeholder="Token name, e.g. release pipeline";
+                            select
+                                name="scope"
+                                class="block p-4 text-sm text-gray-900 border border-gray-300 rounded-lg bg-gray-50 dark:bg-gray-700 dark:border-gray-600 dark:text-white"
+                            {
+                                @for scope in TokenScope::ALL {
+                                    option value=(scope.as_str()) { (scope.as_str()) }
+                                }
+                            }
+                            button
+                                type="submit"
+                                class="text-white bg-blue-700 hover:bg-blue-800 font-medium rounded-lg text-sm px-8 py-2 dark:bg-blue-600 dark:hover:bg-blue-700 whitespace-nowrap"
+                            { "Create token" }
+                        }
+                    }
+                    section class="relative overflow-x-auto shadow-md sm:rounded-lg" {
+                        table class="w-full text-sm text-left rtl:text-right text-gray-500 dark:text-gray-400" {
+                            thead class="text-xs text-gray-700 uppercase bg-gray-50 dark:bg-gray-700 dark:text-gray-400" {
+                                tr {
+                                    th class="px-6 py-3" { "Name" }
+                                    th class="px-6 py-3" { "Scope" }
+                                    th class="px-6 py-3" { "Created At" }
+                                    th class="px-6 py-3" { "Last Used" }
+                                    th class="px-6 py-3" { span class="sr-only" { "Actions" } }
+                                }
+                            }
+                            tbody {
+                                @for token in &self.tokens {
+                                    tr class="bg-white border-b dark:bg-gray-800 dark:border-gray-700 border-gray-200" {
+                                        td class="px-6 py-4 font-medium text-gray-900 dark:text-white" { (token.name) }
+                                        td class="px-6 py-4" { (token.scope.as_str()) }
+                                        td class="px-6 py-4" data-time { (token.created_at.to_string()) }
+                                        @if let Some(last_used_at) = token.last_used_at {
+                                            td class="px-6 py-4" data-time { (last_used_at.to_string()) }
+                                        } @else {
+                                            td class="px-6 py-4" { "Never" }
+                                        }
+                                        td class="px-6 py-4 text-right" {
+                                            form method="post" action=(format!("/tokens/{}/revoke", token.id)) {
+                                                button
+                                                    type="submit"
+                                                    class="font-medium text-red-600 dark:text-red-500 hover:underline"
+                                                { "Revoke" }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        .render_to(buffer);
+    }
+}
+
+impl IntoResponse for TokensPage {
+    fn into_response(self) -> axum::response::Response {
+        self.render().into_response()
+    }
+}